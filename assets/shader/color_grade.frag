@@ -0,0 +1,44 @@
+#version 450
+
+// Color grading through a 3D lookup table. Grading tools work on encoded screenshots, so the color
+// is looked up in sRGB and decoded again afterwards.
+
+layout(std140, set = 0, binding = 0) uniform ColorGradeArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform float strength;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+layout(set = 0, binding = 2) uniform sampler3D lut;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 encode_srgb(vec3 linear){
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(linear, vec3(0.0031308)));
+}
+
+vec3 decode_srgb(vec3 encoded){
+    vec3 low = encoded / 12.92;
+    vec3 high = pow((encoded + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(encoded, vec3(0.04045)));
+}
+
+void main(){
+    vec4 source = texture(color, vertex.tex_coord);
+    vec3 encoded = encode_srgb(clamp(source.rgb, 0.0, 1.0));
+
+    // the outer texels hold the lattice points at 0 and 1, so scale into their centers
+    float size = float(textureSize(lut, 0).x);
+    vec3 lut_coord = encoded * ((size - 1.0) / size) + 0.5 / size;
+    vec3 graded = decode_srgb(texture(lut, lut_coord).rgb);
+
+    out_color = vec4(mix(source.rgb, graded, clamp(strength, 0.0, 1.0)), source.a);
+}
@@ -1,3 +1,13 @@
 #!/bin/sh
-glslc -o fxaa.vert.spv fxaa.vert
+glslc -o fullscreen.vert.spv fullscreen.vert
 glslc -o fxaa.frag.spv fxaa.frag
+glslc -DSTATS -o fxaa_stats.frag.spv fxaa.frag
+glslc -o mask.vert.spv mask.vert
+glslc -o mask.frag.spv mask.frag
+glslc -o downsample.frag.spv downsample.frag
+glslc -o copy.frag.spv copy.frag
+glslc -o sharpen.frag.spv sharpen.frag
+glslc -o color_grade.frag.spv color_grade.frag
+glslc -o dither.frag.spv dither.frag
+glslc -o easu.frag.spv easu.frag
+glslc -o rcas.frag.spv rcas.frag
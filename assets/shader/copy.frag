@@ -0,0 +1,23 @@
+#version 450
+
+// Passes the previous image through, used when the post-processing chain is empty. Copy it as a
+// starting point for a custom pass: the uniform and the input are the same for every custom pass.
+
+layout(std140, set = 0, binding = 0) uniform PostArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform float time;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+void main(){
+    out_color = texture(color, vertex.tex_coord);
+}
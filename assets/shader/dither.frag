@@ -0,0 +1,95 @@
+#version 450
+
+// Dithering and film grain before the image is quantized to the 8-bit output. Noise of one code
+// value is added in sRGB, where the output is quantized, turning the bands of dark gradients into
+// noise too fine to see.
+
+layout(std140, set = 0, binding = 0) uniform DitherArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform uint mode;
+    uniform float grain;
+    uniform bool animated;
+    uniform uint frame;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+layout(set = 0, binding = 2) uniform sampler2D blue_noise;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+#define DITHER_OFF 0u
+#define DITHER_TRIANGULAR 1u
+#define DITHER_BLUE_NOISE 2u
+
+// one code value of the 8-bit output
+#define LSB (1.0 / 255.0)
+
+vec3 encode_srgb(vec3 linear){
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(linear, vec3(0.0031308)));
+}
+
+vec3 decode_srgb(vec3 encoded){
+    vec3 low = encoded / 12.92;
+    vec3 high = pow((encoded + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, lessThanEqual(encoded, vec3(0.04045)));
+}
+
+// uniform noise in [0, 1) from a pixel and a seed
+float hash(vec2 pixel, float seed){
+    vec3 p = fract(vec3(pixel.xyx) * 0.1031 + seed * 0.1337);
+    p += dot(p, p.yzx + 33.33);
+    return fract((p.x + p.y) * p.z);
+}
+
+// uniform noise in [0, 1) reshaped to a triangular distribution in [-1, 1]
+float triangular(float n){
+    float centered = n * 2.0 - 1.0;
+    float shaped = centered * inversesqrt(max(abs(centered), 0.0001));
+    return max(-1.0, shaped) - sign(centered);
+}
+
+vec3 dither_noise(vec2 pixel, float seed){
+    if(mode == DITHER_TRIANGULAR){
+        // the sum of two uniform values, independent per channel
+        return vec3(
+            hash(pixel, seed) + hash(pixel, seed + 1.0),
+            hash(pixel, seed + 2.0) + hash(pixel, seed + 3.0),
+            hash(pixel, seed + 4.0) + hash(pixel, seed + 5.0)) - 1.0;
+    }
+    if(mode == DITHER_BLUE_NOISE){
+        // offset per channel and frame along the R2 sequence by whole texels, which are fetched
+        // unfiltered to keep the tile's spectrum
+        ivec2 tile = textureSize(blue_noise, 0);
+        ivec2 shift = ivec2(fract(seed * vec2(0.7548776662, 0.5698402910)) * vec2(tile));
+        ivec2 texel = ivec2(pixel) + shift;
+        return vec3(
+            triangular(texelFetch(blue_noise, texel % tile, 0).r),
+            triangular(texelFetch(blue_noise, (texel + ivec2(17, 31)) % tile, 0).r),
+            triangular(texelFetch(blue_noise, (texel + ivec2(41, 7)) % tile, 0).r));
+    }
+    return vec3(0.0);
+}
+
+void main(){
+    vec4 source = texture(color, vertex.tex_coord);
+    vec2 pixel = floor(gl_FragCoord.xy);
+    float seed = animated ? float(frame % 1024u) : 0.0;
+
+    vec3 encoded = encode_srgb(clamp(source.rgb, 0.0, 1.0));
+    encoded += dither_noise(pixel, seed) * LSB;
+
+    // monochrome grain, strongest in the mid tones where it reads as film
+    float luma = dot(encoded, vec3(0.2126, 0.7152, 0.0722));
+    float grain_noise = hash(pixel, seed + 64.0) + hash(pixel, seed + 65.0) - 1.0;
+    encoded += grain * grain_noise * 4.0 * luma * (1.0 - luma);
+
+    out_color = vec4(decode_srgb(clamp(encoded, 0.0, 1.0)), source.a);
+}
@@ -0,0 +1,73 @@
+#version 450
+
+// Downsamples the supersampled main pass into the target, for the SSAA reference mode.
+
+layout(std140, set = 0, binding = 0) uniform DownsampleArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform int factor;
+    uniform uint filter_mode;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+#define FILTER_BOX 0u
+#define FILTER_LANCZOS 1u
+
+// Lanczos2, three lobes would need (6 * factor + 1)^2 taps per pixel.
+#define LANCZOS_LOBES 2.0
+#define PI 3.14159265359
+
+float sinc(float x){
+	if(abs(x) < 0.00001){
+		return 1.0;
+	}
+	float px = PI * x;
+	return sin(px) / px;
+}
+
+float lanczos(float x){
+	return abs(x) < LANCZOS_LOBES ? sinc(x) * sinc(x / LANCZOS_LOBES) : 0.0;
+}
+
+void main(){
+	ivec2 maxTexel = ivec2(source_size) - 1;
+
+	// Average of the factor x factor block of samples covering the target pixel.
+	if(filter_mode == FILTER_BOX){
+		ivec2 origin = ivec2(floor(gl_FragCoord.xy)) * factor;
+		vec4 sum = vec4(0.0);
+		for(int y = 0; y < factor; y++){
+			for(int x = 0; x < factor; x++){
+				sum += texelFetch(color, clamp(origin + ivec2(x, y), ivec2(0), maxTexel), 0);
+			}
+		}
+		out_color = sum / float(factor * factor);
+		return;
+	}
+
+	// Lanczos, stretched by the factor so it cuts off at the resolution of the target.
+	vec2 center = gl_FragCoord.xy / target_size * source_size;
+	float radius = LANCZOS_LOBES * float(factor);
+	ivec2 first = ivec2(floor(center - radius));
+	ivec2 last = ivec2(ceil(center + radius));
+	vec4 sum = vec4(0.0);
+	float weightSum = 0.0;
+	for(int y = first.y; y <= last.y; y++){
+		for(int x = first.x; x <= last.x; x++){
+			vec2 distance = (vec2(x, y) + 0.5 - center) / float(factor);
+			float weight = lanczos(distance.x) * lanczos(distance.y);
+			sum += weight * texelFetch(color, clamp(ivec2(x, y), ivec2(0), maxTexel), 0);
+			weightSum += weight;
+		}
+	}
+	// The negative lobes can ring past the displayable range.
+	out_color = clamp(sum / weightSum, 0.0, 1.0);
+}
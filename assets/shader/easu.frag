@@ -0,0 +1,141 @@
+#version 450
+
+// Edge adaptive spatial upsampling after AMD FidelityFX FSR 1 EASU. Twelve texels around the output
+// position are filtered with a Lanczos-like kernel stretched along the local edge direction, then
+// clamped to the nearest four texels to avoid ringing.
+
+layout(std140, set = 0, binding = 0) uniform EasuArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 fetch(ivec2 texel){
+    return texelFetch(color, clamp(texel, ivec2(0), ivec2(source_size) - 1), 0).rgb;
+}
+
+// luma times two, as fsr computes it
+float luma(vec3 c){
+    return c.b * 0.5 + (c.r * 0.5 + c.g);
+}
+
+// accumulate direction and edge length from one of the four bilinear positions around the sample,
+// a is above, b left, c the center, d right and e below
+void set_direction(inout vec2 dir, inout float len, float w, float a, float b, float c, float d, float e){
+    float len_x = max(abs(d - c), abs(c - b));
+    float dir_x = d - b;
+    dir.x += dir_x * w;
+    len_x = clamp(abs(dir_x) / max(len_x, 1.0 / 65536.0), 0.0, 1.0);
+    len += len_x * len_x * w;
+
+    float len_y = max(abs(e - c), abs(c - a));
+    float dir_y = e - a;
+    dir.y += dir_y * w;
+    len_y = clamp(abs(dir_y) / max(len_y, 1.0 / 65536.0), 0.0, 1.0);
+    len += len_y * len_y * w;
+}
+
+// accumulate a texel weighted by the rotated and stretched kernel
+void tap(inout vec3 color_sum, inout float weight_sum, vec2 offset, vec2 dir, vec2 len, float lobe, float clip, vec3 c){
+    vec2 v = vec2(dot(offset, dir), dot(offset, vec2(-dir.y, dir.x))) * len;
+    float d2 = min(dot(v, v), clip);
+    // approximation of lanczos2 without sin or rcp
+    float base = 0.4 * d2 - 1.0;
+    float window = lobe * d2 - 1.0;
+    base *= base;
+    window *= window;
+    base = 1.5625 * base - 0.5625;
+    float w = base * window;
+    weight_sum += w;
+    color_sum += w * c;
+}
+
+void main(){
+    // position in the source relative to the texel f, the top left of the center four
+    //    b c
+    //  e f g h
+    //  i j k l
+    //    n o
+    vec2 pp = floor(gl_FragCoord.xy) * (source_size / target_size) + (0.5 * source_size / target_size - 0.5);
+    vec2 fp = floor(pp);
+    pp -= fp;
+    ivec2 f_texel = ivec2(fp);
+
+    vec3 b = fetch(f_texel + ivec2(0, -1));
+    vec3 c = fetch(f_texel + ivec2(1, -1));
+    vec3 e = fetch(f_texel + ivec2(-1, 0));
+    vec3 f = fetch(f_texel);
+    vec3 g = fetch(f_texel + ivec2(1, 0));
+    vec3 h = fetch(f_texel + ivec2(2, 0));
+    vec3 i = fetch(f_texel + ivec2(-1, 1));
+    vec3 j = fetch(f_texel + ivec2(0, 1));
+    vec3 k = fetch(f_texel + ivec2(1, 1));
+    vec3 l = fetch(f_texel + ivec2(2, 1));
+    vec3 n = fetch(f_texel + ivec2(0, 2));
+    vec3 o = fetch(f_texel + ivec2(1, 2));
+
+    float bl = luma(b);
+    float cl = luma(c);
+    float el = luma(e);
+    float fl = luma(f);
+    float gl = luma(g);
+    float hl = luma(h);
+    float il = luma(i);
+    float jl = luma(j);
+    float kl = luma(k);
+    float ll = luma(l);
+    float nl = luma(n);
+    float ol = luma(o);
+
+    // edge direction and length, bilinearly weighted from the four center texels
+    vec2 dir = vec2(0.0);
+    float len = 0.0;
+    set_direction(dir, len, (1.0 - pp.x) * (1.0 - pp.y), bl, el, fl, gl, jl);
+    set_direction(dir, len, pp.x * (1.0 - pp.y), cl, fl, gl, hl, kl);
+    set_direction(dir, len, (1.0 - pp.x) * pp.y, fl, il, jl, kl, nl);
+    set_direction(dir, len, pp.x * pp.y, gl, jl, kl, ll, ol);
+
+    // normalize the direction, flat areas get an arbitrary one
+    float dir_r = dot(dir, dir);
+    bool flat_area = dir_r < 1.0 / 32768.0;
+    dir = flat_area ? vec2(1.0, 0.0) : dir * inversesqrt(dir_r);
+
+    // stretch the kernel along the edge and shorten its negative lobe on strong edges
+    len = len * 0.5;
+    len *= len;
+    float stretch = dot(dir, dir) / max(abs(dir.x), abs(dir.y));
+    vec2 len2 = vec2(1.0 + (stretch - 1.0) * len, 1.0 - 0.5 * len);
+    float lobe = 0.5 - 0.29 * len;
+    float clip = 1.0 / lobe;
+
+    vec3 color_sum = vec3(0.0);
+    float weight_sum = 0.0;
+    tap(color_sum, weight_sum, vec2(0.0, -1.0) - pp, dir, len2, lobe, clip, b);
+    tap(color_sum, weight_sum, vec2(1.0, -1.0) - pp, dir, len2, lobe, clip, c);
+    tap(color_sum, weight_sum, vec2(-1.0, 1.0) - pp, dir, len2, lobe, clip, i);
+    tap(color_sum, weight_sum, vec2(0.0, 1.0) - pp, dir, len2, lobe, clip, j);
+    tap(color_sum, weight_sum, vec2(0.0, 0.0) - pp, dir, len2, lobe, clip, f);
+    tap(color_sum, weight_sum, vec2(-1.0, 0.0) - pp, dir, len2, lobe, clip, e);
+    tap(color_sum, weight_sum, vec2(1.0, 1.0) - pp, dir, len2, lobe, clip, k);
+    tap(color_sum, weight_sum, vec2(2.0, 1.0) - pp, dir, len2, lobe, clip, l);
+    tap(color_sum, weight_sum, vec2(2.0, 0.0) - pp, dir, len2, lobe, clip, h);
+    tap(color_sum, weight_sum, vec2(1.0, 0.0) - pp, dir, len2, lobe, clip, g);
+    tap(color_sum, weight_sum, vec2(1.0, 2.0) - pp, dir, len2, lobe, clip, o);
+    tap(color_sum, weight_sum, vec2(0.0, 2.0) - pp, dir, len2, lobe, clip, n);
+
+    // deringing, clamp to the range of the center four texels
+    vec3 min4 = min(min(f, g), min(j, k));
+    vec3 max4 = max(max(f, g), max(j, k));
+    vec3 result = min(max4, max(min4, color_sum / weight_sum));
+
+    // alpha is bilinear, the kernel only runs on color
+    out_color = vec4(result, texture(color, vertex.tex_coord).a);
+}
@@ -4,12 +4,34 @@
 // https://github.com/kosua20/Rendu/blob/master/resources/common/shaders/screens/fxaa.frag
 
 layout(std140, set = 0, binding = 0) uniform FXAAUniformArgs {
-    uniform float screen_width;
-    uniform float screen_height;
+    uniform vec2 source_size;
+    uniform vec2 target_size;
     uniform bool enabled;
+    uniform uint luma_mode;
+    uniform bool depth_edges;
+    uniform float depth_threshold;
+    uniform vec2 depth_planes;
+    uniform uint alpha_mode;
+    uniform float edge_threshold_min;
+    uniform float edge_threshold_max;
+    uniform float subpixel_quality;
+    uniform int iterations;
+    uniform uint debug_view;
 };
 
 layout(set = 0, binding = 1) uniform sampler2D color;
+layout(set = 0, binding = 2) uniform sampler2D depth;
+layout(set = 0, binding = 3) uniform sampler2D mask;
+
+// Edge statistics, only counted by the variant compiled with STATS defined.
+#ifdef STATS
+layout(std430, set = 0, binding = 4) buffer FXAAStats {
+    uint pixels;
+    uint edge_pixels;
+    uint search_steps;
+    uint early_outs;
+} counters;
+#endif
 
 layout(location = 0) in VertexData {
     vec3 position;
@@ -19,35 +41,116 @@ layout(location = 0) in VertexData {
 layout(location = 0) out vec4 out_color;
 
 
-#define EDGE_THRESHOLD_MIN 0.0312
-#define EDGE_THRESHOLD_MAX 0.125
+// Edge thresholds, iterations and sub-pixel quality come from the quality preset in the uniform.
 #define QUALITY(q) ((q) < 5 ? 1.0 : ((q) > 5 ? ((q) < 10 ? 2.0 : ((q) < 11 ? 4.0 : 8.0)) : 1.5))
-#define ITERATIONS 12
-#define SUBPIXEL_QUALITY 0.75
 
-// perceptual luma eval
-float rgb2luma(vec3 rgb){
-	return sqrt(dot(rgb, vec3(0.299, 0.587, 0.114)));
+// Luma thresholds are scaled by this inside surfaces when depth edges are used, to keep textures sharp.
+#define DEPTH_SURFACE_THRESHOLD_SCALE 2.0
+
+#define LUMA_PERCEPTUAL 0u
+#define LUMA_LINEAR 1u
+#define LUMA_GREEN 2u
+#define LUMA_ALPHA 3u
+
+#define DEBUG_NONE 0u
+#define DEBUG_LUMA 1u
+#define DEBUG_EDGES 2u
+#define DEBUG_MASK 3u
+
+#define ALPHA_OPAQUE 0u
+#define ALPHA_PRESERVE 1u
+#define ALPHA_ANTIALIAS 2u
+
+// luma eval according to the configured mode
+float rgb2luma(vec4 rgba){
+	if(luma_mode == LUMA_LINEAR){
+		// Rec.709 luminance
+		return dot(rgba.rgb, vec3(0.2126, 0.7152, 0.0722));
+	} else if(luma_mode == LUMA_GREEN){
+		// green carries most of the luminance, cheapest estimate
+		return rgba.g;
+	} else if(luma_mode == LUMA_ALPHA){
+		// luma precomputed by the previous pass
+		return rgba.a;
+	}
+	// perceptual luma
+	return sqrt(dot(rgba.rgb, vec3(0.299, 0.587, 0.114)));
+}
+
+// output alpha according to the configured mode, from the source and the anti-aliased sample
+float outputAlpha(float sourceAlpha, float filteredAlpha){
+	if(alpha_mode == ALPHA_PRESERVE){
+		return sourceAlpha;
+	} else if(alpha_mode == ALPHA_ANTIALIAS){
+		return filteredAlpha;
+	}
+	return 1.0;
+}
+
+// view space distance of a depth buffer value
+float linearDepth(float d){
+	float near = depth_planes.x;
+	float far = depth_planes.y;
+	return near * far / (far - d * (far - near));
+}
+
+// is there a depth discontinuity between this pixel and its direct neighbours
+bool isDepthEdge(vec2 uv){
+	float depthCenter = linearDepth(textureLod(depth, uv, 0.0).r);
+	float depthDown 	= linearDepth(textureLodOffset(depth, uv, 0.0, ivec2( 0,-1)).r);
+	float depthUp 		= linearDepth(textureLodOffset(depth, uv, 0.0, ivec2( 0, 1)).r);
+	float depthLeft 	= linearDepth(textureLodOffset(depth, uv, 0.0, ivec2(-1, 0)).r);
+	float depthRight 	= linearDepth(textureLodOffset(depth, uv, 0.0, ivec2( 1, 0)).r);
+	float depthDelta = max(max(abs(depthDown - depthCenter), abs(depthUp - depthCenter)), max(abs(depthLeft - depthCenter), abs(depthRight - depthCenter)));
+	// The threshold grows with distance, so far away geometry isn't treated as all edges.
+	return depthDelta > depth_threshold * depthCenter;
 }
 
 void main(){
-    if(!enabled){
-        out_color = texture(color, vertex.tex_coord);
+    // Texture coordinate of the target pixel center, the source may have a different resolution.
+    vec2 texCoord = gl_FragCoord.xy / target_size;
+
+    // Luma as used for edge detection.
+    if(debug_view == DEBUG_LUMA){
+        out_color = vec4(vec3(rgb2luma(texture(color, texCoord))), 1.0);
+        return;
+    }
+    // Excluded pixels in red and depth edges in green, over the dimmed luma.
+    if(debug_view == DEBUG_MASK){
+        vec3 debugColor = vec3(0.5 * rgb2luma(texture(color, texCoord)));
+        if(textureLod(mask, texCoord, 0.0).r > 0.5){
+            debugColor.r = 1.0;
+        }
+        if(depth_edges && isDepthEdge(texCoord)){
+            debugColor.g = 1.0;
+        }
+        out_color = vec4(debugColor, 1.0);
+        return;
+    }
+
+    // Pixels flagged in the exclusion mask are passed through untouched.
+    if(!enabled || textureLod(mask, texCoord, 0.0).r > 0.5){
+        vec4 source = texture(color, texCoord);
+        out_color = vec4(source.rgb, outputAlpha(source.a, source.a));
+        if(debug_view == DEBUG_EDGES){
+            out_color = vec4(vec3(0.25 * rgb2luma(source)), 1.0);
+        }
         return;
     }
 
-    vec2 inverseScreenSize = 1.0/vec2(screen_width,screen_height);
+    // Step size of one texel in the source image.
+    vec2 inverseScreenSize = 1.0/source_size;
 
-    vec3 colorCenter = texture(color,vertex.tex_coord).rgb;
+    vec4 colorCenter = texture(color,texCoord);
 	
 	// Luma at the current fragment
 	float lumaCenter = rgb2luma(colorCenter);
 	
 	// Luma at the four direct neighbours of the current fragment.
-	float lumaDown 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2( 0,-1)).rgb);
-	float lumaUp 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2( 0, 1)).rgb);
-	float lumaLeft 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2(-1, 0)).rgb);
-	float lumaRight = rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2( 1, 0)).rgb);
+	float lumaDown 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2( 0,-1)));
+	float lumaUp 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2( 0, 1)));
+	float lumaLeft 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2(-1, 0)));
+	float lumaRight = rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2( 1, 0)));
 	
 	// Find the maximum and minimum luma around the current fragment.
 	float lumaMin = min(lumaCenter,min(min(lumaDown,lumaUp),min(lumaLeft,lumaRight)));
@@ -56,17 +159,28 @@ void main(){
 	// Compute the delta.
 	float lumaRange = lumaMax - lumaMin;
 	
+	// With depth edges, geometry silhouettes are always processed and the luma test gets stricter inside surfaces.
+	bool depthEdge = depth_edges && isDepthEdge(texCoord);
+	float thresholdScale = (depth_edges && !depthEdge) ? DEPTH_SURFACE_THRESHOLD_SCALE : 1.0;
+	
 	// If the luma variation is lower that a threshold (or if we are in a really dark area), we are not on an edge, don't perform any AA.
-	if(lumaRange < max(EDGE_THRESHOLD_MIN,lumaMax*EDGE_THRESHOLD_MAX)){
-		out_color = vec4(colorCenter,1.0);
+	if(!depthEdge && lumaRange < thresholdScale * max(edge_threshold_min,lumaMax*edge_threshold_max)){
+#ifdef STATS
+		atomicAdd(counters.pixels, 1u);
+		atomicAdd(counters.early_outs, 1u);
+#endif
+		out_color = vec4(colorCenter.rgb,outputAlpha(colorCenter.a,colorCenter.a));
+		if(debug_view == DEBUG_EDGES){
+			out_color = vec4(vec3(0.25 * lumaCenter), 1.0);
+		}
 		return;
 	}
 	
 	// Query the 4 remaining corners lumas.
-	float lumaDownLeft 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2(-1,-1)).rgb);
-	float lumaUpRight 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2( 1, 1)).rgb);
-	float lumaUpLeft 	= rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2(-1, 1)).rgb);
-	float lumaDownRight = rgb2luma(textureLodOffset(color,vertex.tex_coord, 0.0,ivec2( 1,-1)).rgb);
+	float lumaDownLeft 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2(-1,-1)));
+	float lumaUpRight 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2( 1, 1)));
+	float lumaUpLeft 	= rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2(-1, 1)));
+	float lumaDownRight = rgb2luma(textureLodOffset(color,texCoord, 0.0,ivec2( 1,-1)));
 	
 	// Combine the four edges lumas (using intermediary variables for future computations with the same values).
 	float lumaDownUp = lumaDown + lumaUp;
@@ -112,7 +226,7 @@ void main(){
 	}
 	
 	// Shift UV in the correct direction by half a pixel.
-	vec2 currentUv = vertex.tex_coord;
+	vec2 currentUv = texCoord;
 	if(isHorizontal){
 		currentUv.y += stepLength * 0.5;
 	} else {
@@ -126,8 +240,8 @@ void main(){
 	vec2 uv2 = currentUv + offset * QUALITY(0);
 	
 	// Read the lumas at both current extremities of the exploration segment, and compute the delta wrt to the local average luma.
-	float lumaEnd1 = rgb2luma(textureLod(color,uv1, 0.0).rgb);
-	float lumaEnd2 = rgb2luma(textureLod(color,uv2, 0.0).rgb);
+	float lumaEnd1 = rgb2luma(textureLod(color,uv1, 0.0));
+	float lumaEnd2 = rgb2luma(textureLod(color,uv2, 0.0));
 	lumaEnd1 -= lumaLocalAverage;
 	lumaEnd2 -= lumaLocalAverage;
 	
@@ -136,6 +250,9 @@ void main(){
 	bool reached2 = abs(lumaEnd2) >= gradientScaled;
 	bool reachedBoth = reached1 && reached2;
 	
+	// Number of search steps taken, for the statistics.
+	uint searchSteps = 1u;
+	
 	// If the side is not reached, we continue to explore in this direction.
 	if(!reached1){
 		uv1 -= offset * QUALITY(1);
@@ -147,15 +264,16 @@ void main(){
 	// If both sides have not been reached, continue to explore.
 	if(!reachedBoth){
 		
-		for(int i = 2; i < ITERATIONS; i++){
+		for(int i = 2; i < iterations; i++){
+			searchSteps++;
 			// If needed, read luma in 1st direction, compute delta.
 			if(!reached1){
-				lumaEnd1 = rgb2luma(textureLod(color, uv1, 0.0).rgb);
+				lumaEnd1 = rgb2luma(textureLod(color, uv1, 0.0));
 				lumaEnd1 = lumaEnd1 - lumaLocalAverage;
 			}
 			// If needed, read luma in opposite direction, compute delta.
 			if(!reached2){
-				lumaEnd2 = rgb2luma(textureLod(color, uv2, 0.0).rgb);
+				lumaEnd2 = rgb2luma(textureLod(color, uv2, 0.0));
 				lumaEnd2 = lumaEnd2 - lumaLocalAverage;
 			}
 			// If the luma deltas at the current extremities is larger than the local gradient, we have reached the side of the edge.
@@ -178,8 +296,8 @@ void main(){
 	}
 	
 	// Compute the distances to each side edge of the edge (!).
-	float distance1 = isHorizontal ? (vertex.tex_coord.x - uv1.x) : (vertex.tex_coord.y - uv1.y);
-	float distance2 = isHorizontal ? (uv2.x - vertex.tex_coord.x) : (uv2.y - vertex.tex_coord.y);
+	float distance1 = isHorizontal ? (texCoord.x - uv1.x) : (texCoord.y - uv1.y);
+	float distance2 = isHorizontal ? (uv2.x - texCoord.x) : (uv2.y - texCoord.y);
 	
 	// In which direction is the side of the edge closer ?
 	bool isDirection1 = distance1 < distance2;
@@ -208,16 +326,20 @@ void main(){
 	// Full weighted average of the luma over the 3x3 neighborhood.
 	float lumaAverage = (1.0/12.0) * (2.0 * (lumaDownUp + lumaLeftRight) + lumaLeftCorners + lumaRightCorners);
 	// Ratio of the delta between the global average and the center luma, over the luma range in the 3x3 neighborhood.
-	float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter)/lumaRange,0.0,1.0);
+	float subPixelOffset1 = clamp(abs(lumaAverage - lumaCenter)/max(lumaRange,0.0001),0.0,1.0);
 	float subPixelOffset2 = (-2.0 * subPixelOffset1 + 3.0) * subPixelOffset1 * subPixelOffset1;
 	// Compute a sub-pixel offset based on this delta.
-	float subPixelOffsetFinal = subPixelOffset2 * subPixelOffset2 * SUBPIXEL_QUALITY;
+	float subPixelOffsetFinal = subPixelOffset2 * subPixelOffset2 * subpixel_quality;
+	// Sub-pixel blending is what blurs texture detail, only keep it on silhouettes when depth is known.
+	if(depth_edges && !depthEdge){
+		subPixelOffsetFinal = 0.0;
+	}
 	
 	// Pick the biggest of the two offsets.
 	finalOffset = max(finalOffset,subPixelOffsetFinal);
 	
 	// Compute the final UV coordinates.
-	vec2 finalUv = vertex.tex_coord;
+	vec2 finalUv = texCoord;
 	if(isHorizontal){
 		finalUv.y += finalOffset * stepLength;
 	} else {
@@ -225,6 +347,17 @@ void main(){
 	}
 	
 	// Read the color at the new UV coordinates, and use it.
-	vec3 finalColor = textureLod(color,finalUv, 0.0).rgb;
-	out_color = vec4(finalColor,1.0);
+	vec4 finalColor = textureLod(color,finalUv, 0.0);
+	out_color = vec4(finalColor.rgb,outputAlpha(colorCenter.a,finalColor.a));
+	// Horizontal edges in red and vertical edges in blue, brighter the further the sample moved.
+	if(debug_view == DEBUG_EDGES){
+		float intensity = 0.4 + 1.2 * finalOffset;
+		out_color = isHorizontal ? vec4(intensity, 0.0, 0.0, 1.0) : vec4(0.0, 0.0, intensity, 1.0);
+	}
+	
+#ifdef STATS
+	atomicAdd(counters.pixels, 1u);
+	atomicAdd(counters.edge_pixels, 1u);
+	atomicAdd(counters.search_steps, searchSteps);
+#endif
 }
\ No newline at end of file
@@ -0,0 +1,8 @@
+#version 450
+
+// pixels covered by excluded entities are flagged in the red channel of the mask
+layout(location = 0) out vec4 out_mask;
+
+void main() {
+    out_mask = vec4(1.0);
+}
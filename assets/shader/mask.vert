@@ -0,0 +1,23 @@
+#version 450
+
+// same block as the view arguments of the main pass
+layout(std140, set = 0, binding = 0) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+    uniform mat4 proj_view;
+};
+
+layout(push_constant) uniform Model {
+    mat4 model;
+};
+
+layout(location = 0) in vec3 position;
+
+// the mask is depth tested for equality against the main pass, so the position is computed like
+// its vertex shaders do and may not be reordered
+invariant gl_Position;
+
+void main() {
+    vec4 vertex_position = model * vec4(position, 1.0);
+    gl_Position = proj_view * vertex_position;
+}
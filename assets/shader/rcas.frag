@@ -0,0 +1,69 @@
+#version 450
+
+// Robust contrast adaptive sharpening after AMD FidelityFX FSR 1 RCAS, run on the output of EASU.
+// The negative lobe is limited so that no output exceeds the range of its neighbours, and reduced
+// on noise so grain isn't amplified.
+
+layout(std140, set = 0, binding = 0) uniform RcasArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform float sharpness;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+// strongest lobe that still can't clip
+#define RCAS_LIMIT (0.25 - 1.0 / 16.0)
+
+vec3 fetch(ivec2 texel){
+    return texelFetch(color, clamp(texel, ivec2(0), ivec2(source_size) - 1), 0).rgb;
+}
+
+float luma(vec3 c){
+    return c.b * 0.5 + (c.r * 0.5 + c.g);
+}
+
+void main(){
+    // cross around the pixel
+    //    b
+    //  d e f
+    //    h
+    ivec2 texel = ivec2(gl_FragCoord.xy);
+    vec3 b = fetch(texel + ivec2(0, -1));
+    vec3 d = fetch(texel + ivec2(-1, 0));
+    vec4 center = texelFetch(color, clamp(texel, ivec2(0), ivec2(source_size) - 1), 0);
+    vec3 e = center.rgb;
+    vec3 f = fetch(texel + ivec2(1, 0));
+    vec3 h = fetch(texel + ivec2(0, 1));
+
+    // noise detection, a pixel that stands out from all its neighbours gets less sharpening
+    float bl = luma(b);
+    float dl = luma(d);
+    float el = luma(e);
+    float fl = luma(f);
+    float hl = luma(h);
+    float range = max(max(max(bl, dl), max(el, fl)), hl) - min(min(min(bl, dl), min(el, fl)), hl);
+    float noise = 0.25 * (bl + dl + fl + hl) - el;
+    noise = clamp(abs(noise) / max(range, 1.0 / 65536.0), 0.0, 1.0);
+    noise = -0.5 * noise + 1.0;
+
+    // largest lobe for which the result stays within the neighbours' range
+    vec3 min4 = min(min(b, d), min(f, h));
+    vec3 max4 = max(max(b, d), max(f, h));
+    vec3 hit_min = min4 / max(4.0 * max4, 1.0 / 65536.0);
+    vec3 hit_max = (1.0 - max4) / min(4.0 * min4 - 4.0, -1.0 / 65536.0);
+    vec3 lobe_rgb = max(-hit_min, hit_max);
+    float lobe = max(-RCAS_LIMIT, min(max(lobe_rgb.r, max(lobe_rgb.g, lobe_rgb.b)), 0.0));
+    // sharpness in stops, 0 is the strongest
+    lobe *= exp2(-max(sharpness, 0.0)) * noise;
+
+    vec3 result = (lobe * (b + d + f + h) + e) / (4.0 * lobe + 1.0);
+    out_color = vec4(result, center.a);
+}
@@ -0,0 +1,61 @@
+#version 450
+
+// Contrast adaptive sharpening after FXAA, following AMD FidelityFX CAS. The negative lobe shrinks
+// where the neighbourhood already spans a large range, so edges FXAA smoothed aren't sharpened back
+// into jaggies while low contrast texture detail regains its crispness.
+
+layout(std140, set = 0, binding = 0) uniform SharpenArgs {
+    uniform vec2 source_size;
+    uniform vec2 target_size;
+    uniform float strength;
+};
+
+layout(set = 0, binding = 1) uniform sampler2D color;
+
+layout(location = 0) in VertexData {
+    vec3 position;
+    vec2 tex_coord;
+} vertex;
+
+layout(location = 0) out vec4 out_color;
+
+// weight of the negative lobe at full strength, the strongest CAS setting
+#define MAX_LOBE 0.2
+
+vec3 fetch(vec2 offset){
+    return texture(color, vertex.tex_coord + offset / source_size).rgb;
+}
+
+void main(){
+    // 3x3 neighbourhood
+    //  a b c
+    //  d e f
+    //  g h i
+    vec3 a = fetch(vec2(-1.0, -1.0));
+    vec3 b = fetch(vec2( 0.0, -1.0));
+    vec3 c = fetch(vec2( 1.0, -1.0));
+    vec3 d = fetch(vec2(-1.0,  0.0));
+    vec4 center = texture(color, vertex.tex_coord);
+    vec3 e = center.rgb;
+    vec3 f = fetch(vec2( 1.0,  0.0));
+    vec3 g = fetch(vec2(-1.0,  1.0));
+    vec3 h = fetch(vec2( 0.0,  1.0));
+    vec3 i = fetch(vec2( 1.0,  1.0));
+
+    // soft minimum and maximum, the cross plus the whole neighbourhood, in the range 0 to 2
+    vec3 min_cross = min(min(min(d, e), min(f, b)), h);
+    vec3 min_rgb = min_cross + min(min_cross, min(min(a, c), min(g, i)));
+    vec3 max_cross = max(max(max(d, e), max(f, b)), h);
+    vec3 max_rgb = max_cross + max(max_cross, max(max(a, c), max(g, i)));
+
+    // sharpening amount from the distance to the signal limits, small on strong edges
+    vec3 amount = sqrt(clamp(min(min_rgb, 2.0 - max_rgb) / max(max_rgb, 0.0001), 0.0, 1.0));
+
+    // filter shape
+    //  0 w 0
+    //  w 1 w
+    //  0 w 0
+    vec3 w = amount * -(MAX_LOBE * clamp(strength, 0.0, 1.0));
+    vec3 sharpened = ((b + d + f + h) * w + e) / (1.0 + 4.0 * w);
+    out_color = vec4(clamp(sharpened, 0.0, 1.0), center.a);
+}
@@ -0,0 +1,131 @@
+// adaptive fxaa quality
+// the gpu time of the fxaa pass, measured by the profiling timestamps, is compared against a budget
+// and the quality preset is stepped down when over it and back up when there is enough headroom
+
+use crate::{fxaa::FxaaQuality, profiling::PassTimings, FxaaSettings};
+use amethyst::{
+    core::Time,
+    ecs::prelude::{Read, System, SystemData, Write},
+    log,
+};
+use serde::{Deserialize, Serialize};
+
+/// Adaptive quality configuration, loaded from `config/adaptive_quality.ron`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AdaptiveQualityConfig {
+    pub enabled: bool,
+    /// Gpu time budget of the fxaa pass in milliseconds.
+    pub budget: f32,
+    /// Fraction of the budget the pass has to stay under before a higher preset is tried.
+    pub headroom: f32,
+    /// Seconds to measure a preset before it may change again.
+    pub hold: f32,
+    pub min_quality: FxaaQuality,
+    pub max_quality: FxaaQuality,
+}
+
+impl Default for AdaptiveQualityConfig {
+    fn default() -> Self {
+        AdaptiveQualityConfig {
+            enabled: false,
+            budget: 0.5,
+            headroom: 0.7,
+            hold: 1.0,
+            min_quality: FxaaQuality::Low,
+            max_quality: FxaaQuality::Ultra,
+        }
+    }
+}
+
+// system moving the fxaa quality preset to stay within the gpu time budget
+pub struct AdaptiveQualitySystem {
+    // smoothed fxaa pass time in milliseconds
+    average: Option<f32>,
+    // last frame taken into the average
+    frame: Option<u64>,
+    // frames taken into the average since the preset changed
+    samples: usize,
+    // seconds left before the preset may change again
+    cooldown: f32,
+    warned: bool,
+}
+
+impl Default for AdaptiveQualitySystem {
+    fn default() -> Self {
+        AdaptiveQualitySystem {
+            average: None,
+            frame: None,
+            samples: 0,
+            cooldown: 0.0,
+            warned: false,
+        }
+    }
+}
+
+impl AdaptiveQualitySystem {
+    // a preset is judged on at least this many frames
+    const MIN_SAMPLES: usize = 30;
+}
+
+impl<'s> System<'s> for AdaptiveQualitySystem {
+    type SystemData = (
+        Read<'s, Time>,
+        Read<'s, AdaptiveQualityConfig>,
+        Read<'s, PassTimings>,
+        Write<'s, FxaaSettings>,
+    );
+
+    fn run(&mut self, (time, config, timings, mut fxaa_settings): Self::SystemData) {
+        if !config.enabled || !fxaa_settings.enabled {
+            return;
+        }
+        if timings.available == Some(false) {
+            if !self.warned {
+                log::warn!("GPU pass timings unavailable, adaptive FXAA quality is disabled");
+                self.warned = true;
+            }
+            return;
+        }
+
+        // timings of frames rendered with the previous preset are skipped while cooling down
+        self.cooldown -= time.delta_real_seconds();
+        if let Some(latest) = timings.latest {
+            if self.cooldown <= 0.0 && self.frame.map_or(true, |frame| latest.frame > frame) {
+                self.frame = Some(latest.frame);
+                self.samples += 1;
+                self.average = Some(match self.average {
+                    Some(average) => average * 0.9 + latest.fxaa * 0.1,
+                    None => latest.fxaa,
+                });
+            }
+        }
+        let average = match self.average {
+            Some(average) if self.samples >= Self::MIN_SAMPLES => average,
+            _ => return,
+        };
+
+        // the gap between the budget and the headroom keeps the preset from oscillating
+        let current = fxaa_settings.quality;
+        let quality = if average > config.budget || current > config.max_quality {
+            current.lower().filter(|q| *q >= config.min_quality)
+        } else if average < config.budget * config.headroom || current < config.min_quality {
+            current.higher().filter(|q| *q <= config.max_quality)
+        } else {
+            None
+        };
+        if let Some(quality) = quality {
+            log::info!(
+                "FXAA quality {:?} -> {:?} ({:.3}ms fxaa pass, {:.3}ms budget)",
+                current,
+                quality,
+                average,
+                config.budget
+            );
+            fxaa_settings.quality = quality;
+            self.cooldown = config.hold;
+            self.average = None;
+            self.samples = 0;
+        }
+    }
+}
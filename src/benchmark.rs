@@ -0,0 +1,285 @@
+// benchmark mode
+// renders the scene with fxaa off, with every quality preset, sharpened and supersampled, each run
+// starting with warm-up frames that aren't measured, and writes cpu frame times and gpu pass times to
+// a json report
+
+use crate::{
+    fxaa::FxaaQuality,
+    postprocess::{PostEffect, PostProcessChain},
+    profiling::PassTimings,
+    scale::RenderScale,
+    ssaa::SsaaSettings,
+    FxaaSettings,
+};
+use amethyst::{
+    core::Time,
+    ecs::{World, WorldExt},
+    log,
+    window::ScreenDimensions,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::PathBuf;
+
+/// Benchmark configuration, loaded from `config/benchmark.ron` and enabled with `--benchmark`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BenchmarkConfig {
+    pub enabled: bool,
+    /// Scene prefab loaded from the assets directory.
+    pub scene: String,
+    /// Frames rendered after switching modes before measuring starts.
+    pub warmup_frames: u64,
+    /// Frames measured for every mode.
+    pub frames: u64,
+    /// File the json report is written to.
+    pub output: PathBuf,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            enabled: false,
+            scene: "scene.ron".to_string(),
+            warmup_frames: 60,
+            frames: 300,
+            output: PathBuf::from("benchmark.json"),
+        }
+    }
+}
+
+/// Summary of a series of timings, in milliseconds.
+#[derive(Clone, Debug, Serialize)]
+pub struct TimingSummary {
+    pub samples: usize,
+    pub average: f32,
+    pub min: f32,
+    pub max: f32,
+    pub p50: f32,
+    pub p95: f32,
+    pub p99: f32,
+}
+
+impl TimingSummary {
+    fn new(samples: &[f32]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |percentile: f32| {
+            let rank = (percentile / 100.0 * sorted.len() as f32).ceil() as usize;
+            sorted[rank.max(1).min(sorted.len()) - 1]
+        };
+        Some(TimingSummary {
+            samples: sorted.len(),
+            average: sorted.iter().sum::<f32>() / sorted.len() as f32,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            p50: percentile(50.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+        })
+    }
+}
+
+/// Measurements of a single mode.
+#[derive(Clone, Debug, Serialize)]
+pub struct RunReport {
+    pub fxaa: bool,
+    pub quality: Option<FxaaQuality>,
+    /// The sharpening pass runs after fxaa.
+    pub sharpen: bool,
+    /// Supersampling factor, replacing fxaa.
+    pub ssaa: Option<u32>,
+    pub cpu_frame: Option<TimingSummary>,
+    /// Gpu pass times, missing when the device can't write timestamps. The fxaa time covers the
+    /// anti-aliasing pass alone, with supersampling the downsample pass.
+    pub gpu_main: Option<TimingSummary>,
+    pub gpu_fxaa: Option<TimingSummary>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BenchmarkReport {
+    pub scene: String,
+    pub backend: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub render_scale: f32,
+    pub warmup_frames: u64,
+    pub frames: u64,
+    pub runs: Vec<RunReport>,
+}
+
+#[derive(Default)]
+struct Run {
+    fxaa: bool,
+    quality: Option<FxaaQuality>,
+    sharpen: bool,
+    ssaa: Option<u32>,
+    cpu_frame: Vec<f32>,
+    gpu_main: Vec<f32>,
+    gpu_fxaa: Vec<f32>,
+}
+
+/// Drives the runs from the state's update, one frame at a time.
+pub struct Benchmark {
+    config: BenchmarkConfig,
+    runs: Vec<Run>,
+    // index of the run being rendered
+    current: usize,
+    // frames rendered in the current run, including warm-up
+    frame: u64,
+    // gpu timings of frames up to this one belong to an earlier run or the warm-up
+    gpu_frame: Option<u64>,
+}
+
+impl Benchmark {
+    pub fn new(config: BenchmarkConfig) -> Self {
+        let mut runs = vec![Run {
+            fxaa: false,
+            ..Default::default()
+        }];
+        runs.extend(FxaaQuality::ALL.iter().map(|quality| Run {
+            fxaa: true,
+            quality: Some(*quality),
+            ..Default::default()
+        }));
+        runs.push(Run {
+            fxaa: true,
+            quality: Some(FxaaQuality::default()),
+            sharpen: true,
+            ..Default::default()
+        });
+        runs.extend((SsaaSettings::MIN_FACTOR..=SsaaSettings::MAX_FACTOR).map(|factor| Run {
+            ssaa: Some(factor),
+            ..Default::default()
+        }));
+        Benchmark {
+            config,
+            runs,
+            current: 0,
+            frame: 0,
+            gpu_frame: None,
+        }
+    }
+
+    /// Advance by a frame, returns true once every run is measured and the report is written.
+    pub fn update(&mut self, world: &World) -> bool {
+        if self.frame == 0 {
+            self.start_run(world);
+        }
+        self.frame += 1;
+
+        let timings = world.read_resource::<PassTimings>();
+        let latest = timings.latest;
+        drop(timings);
+        if self.frame <= self.config.warmup_frames {
+            // frames resolved during the warm-up were rendered with the previous mode
+            self.gpu_frame = latest.map(|timing| timing.frame);
+            return false;
+        }
+
+        let delta = world.read_resource::<Time>().delta_real_seconds() * 1000.0;
+        let run = &mut self.runs[self.current];
+        run.cpu_frame.push(delta);
+        if let Some(timing) = latest {
+            if self.gpu_frame.map_or(true, |frame| timing.frame > frame) {
+                self.gpu_frame = Some(timing.frame);
+                run.gpu_main.push(timing.main);
+                run.gpu_fxaa.push(timing.fxaa);
+            }
+        }
+
+        if self.frame < self.config.warmup_frames + self.config.frames {
+            return false;
+        }
+        self.current += 1;
+        self.frame = 0;
+        if self.current < self.runs.len() {
+            return false;
+        }
+
+        self.write_report(world);
+        true
+    }
+
+    fn start_run(&self, world: &World) {
+        let run = &self.runs[self.current];
+        let mut fxaa_settings = world.write_resource::<FxaaSettings>();
+        fxaa_settings.enabled = run.fxaa;
+        if let Some(quality) = run.quality {
+            fxaa_settings.quality = quality;
+        }
+        let mut ssaa_settings = world.write_resource::<SsaaSettings>();
+        ssaa_settings.enabled = run.ssaa.is_some();
+        if let Some(factor) = run.ssaa {
+            ssaa_settings.factor = factor;
+        }
+        if let Some(mut chain) = world.try_fetch_mut::<PostProcessChain>() {
+            chain.set_enabled(PostEffect::Sharpen, run.sharpen);
+        }
+        log::info!(
+            "Benchmark run {}/{}: fxaa {}, quality {:?}, sharpen {}, ssaa {:?}",
+            self.current + 1,
+            self.runs.len(),
+            run.fxaa,
+            run.quality,
+            run.sharpen,
+            run.ssaa
+        );
+    }
+
+    fn write_report(&self, world: &World) {
+        let (width, height) = world
+            .try_fetch::<ScreenDimensions>()
+            .map_or((0, 0), |dimensions| {
+                (dimensions.width() as u32, dimensions.height() as u32)
+            });
+        let render_scale = world
+            .try_fetch::<RenderScale>()
+            .map_or(1.0, |render_scale| render_scale.scale);
+
+        let report = BenchmarkReport {
+            scene: self.config.scene.clone(),
+            backend: backend_name(),
+            width,
+            height,
+            render_scale,
+            warmup_frames: self.config.warmup_frames,
+            frames: self.config.frames,
+            runs: self
+                .runs
+                .iter()
+                .map(|run| RunReport {
+                    fxaa: run.fxaa,
+                    quality: run.quality,
+                    sharpen: run.sharpen,
+                    ssaa: run.ssaa,
+                    cpu_frame: TimingSummary::new(&run.cpu_frame),
+                    gpu_main: TimingSummary::new(&run.gpu_main),
+                    gpu_fxaa: TimingSummary::new(&run.gpu_fxaa),
+                })
+                .collect(),
+        };
+
+        let path = &self.config.output;
+        let result = File::create(path)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(file, &report));
+        match result {
+            Ok(()) => log::info!("Benchmark report written to {:?}", path),
+            Err(err) => log::error!("Failed to write benchmark report {:?}: {}", path, err),
+        }
+    }
+}
+
+fn backend_name() -> &'static str {
+    if cfg!(feature = "vulkan") {
+        "vulkan"
+    } else if cfg!(feature = "metal") {
+        "metal"
+    } else {
+        "empty"
+    }
+}
@@ -0,0 +1,248 @@
+// command line options
+// every option overrides the matching setting loaded from the config directory, so automated runs
+// and bug reports can be scripted without editing ron files
+
+use crate::color_grade::LutOptions;
+use crate::fxaa::{DebugView, FxaaQuality};
+use crate::quality::QualityOptions;
+use amethyst::Error;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: amethyst-fxaa [OPTIONS]
+
+Options:
+    --scene <NAME>            bundled scene name or prefab path ending in .ron
+    --size <WIDTHxHEIGHT>     window or offscreen target size
+    --fullscreen              start fullscreen on the current monitor
+    --aa <MODE>               off, low, medium, high, ultra, a preset with +sharpen (e.g.
+                              high+sharpen) or ssaa2, ssaa3, ssaa4
+    --debug-view <VIEW>       off, luma, edges or mask
+    --screenshot-after <N>    save a screenshot after N frames
+    --frames <N>              exit after N frames
+    --headless                render offscreen without a window
+    --benchmark               measure every anti-aliasing mode and write a report
+    --help                    print this message
+
+Quality evaluation without rendering:
+    amethyst-fxaa quality --reference <PNG> --aliased <PNG> [OPTIONS]
+
+    --reference <PNG>         supersampled frame, an integer multiple of the aliased size
+    --aliased <PNG>           frame without anti-aliasing
+    --fxaa <PNG>              anti-aliased frame, computed on the cpu when missing
+    --quality <PRESET>        low, medium, high or ultra preset of the cpu fxaa
+    --output <DIR>            directory for the heatmaps and metrics.json
+    --ppd <N>                 pixels per degree of visual angle for flip
+
+Identity color grading table to grade in an external tool:
+    amethyst-fxaa lut [--size <N>] [--output <PATH>]
+
+    --size <N>                entries along each axis, 2 to 65, 32 by default
+    --output <PATH>           .cube file or strip png, identity.png by default
+
+The graphics backend is chosen at build time with the vulkan or metal cargo feature.";
+
+/// Anti-aliasing the application starts with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AntiAliasing {
+    Off,
+    Fxaa(FxaaQuality),
+    /// Fxaa followed by the sharpening pass.
+    FxaaSharpen(FxaaQuality),
+    /// Supersampling at the given factor.
+    Ssaa(u32),
+}
+
+/// Options parsed from the command line, unset ones keep their configured value.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub scene: Option<String>,
+    pub size: Option<(u32, u32)>,
+    pub fullscreen: bool,
+    pub anti_aliasing: Option<AntiAliasing>,
+    pub debug_view: Option<DebugView>,
+    pub screenshot_after: Option<u64>,
+    pub frames: Option<u64>,
+    pub headless: bool,
+    pub benchmark: bool,
+    pub help: bool,
+    /// Evaluate image quality instead of running the application.
+    pub quality: Option<QualityOptions>,
+    /// Write an identity lookup table instead of running the application.
+    pub lut: Option<LutOptions>,
+}
+
+impl Options {
+    /// Parse the arguments following the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, Error> {
+        let mut options = Options::default();
+        let mut args = args.into_iter().peekable();
+        if args.peek().map(String::as_str) == Some("quality") {
+            args.next();
+            options.quality = Some(parse_quality(args)?);
+            return Ok(options);
+        }
+        if args.peek().map(String::as_str) == Some("lut") {
+            args.next();
+            options.lut = Some(parse_lut(args)?);
+            return Ok(options);
+        }
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| Error::from_string(format!("Missing value for {}", arg)))
+            };
+            match arg.as_str() {
+                "--scene" => options.scene = Some(value()?),
+                "--size" => options.size = Some(parse_size(&value()?)?),
+                "--fullscreen" => options.fullscreen = true,
+                "--aa" => options.anti_aliasing = Some(parse_anti_aliasing(&value()?)?),
+                "--debug-view" => options.debug_view = Some(parse_debug_view(&value()?)?),
+                "--screenshot-after" => options.screenshot_after = Some(parse_count(&value()?)?),
+                "--frames" => options.frames = Some(parse_count(&value()?)?),
+                "--headless" => options.headless = true,
+                "--benchmark" => options.benchmark = true,
+                "--help" | "-h" => options.help = true,
+                _ => {
+                    return Err(Error::from_string(format!(
+                        "Unknown argument {}\n\n{}",
+                        arg, USAGE
+                    )))
+                }
+            }
+        }
+        Ok(options)
+    }
+}
+
+fn parse_quality<I: Iterator<Item = String>>(mut args: I) -> Result<QualityOptions, Error> {
+    let mut options = QualityOptions::default();
+    let (mut reference, mut aliased) = (None, None);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Error::from_string(format!("Missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--reference" => reference = Some(PathBuf::from(value()?)),
+            "--aliased" => aliased = Some(PathBuf::from(value()?)),
+            "--fxaa" => options.fxaa = Some(PathBuf::from(value()?)),
+            "--quality" => match parse_anti_aliasing(&value()?)? {
+                AntiAliasing::Fxaa(quality) => options.quality = quality,
+                AntiAliasing::FxaaSharpen(_) => {
+                    return Err(Error::from_string("The cpu fxaa doesn't sharpen"))
+                }
+                AntiAliasing::Off | AntiAliasing::Ssaa(_) => {
+                    return Err(Error::from_string("The cpu fxaa needs a preset"))
+                }
+            },
+            "--output" => options.output = PathBuf::from(value()?),
+            "--ppd" => {
+                let ppd = value()?;
+                options.pixels_per_degree = ppd
+                    .parse()
+                    .ok()
+                    .filter(|ppd: &f32| *ppd > 0.0)
+                    .ok_or_else(|| Error::from_string(format!("Invalid pixels per degree {}", ppd)))?;
+            }
+            _ => {
+                return Err(Error::from_string(format!(
+                    "Unknown argument {}\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+    match (reference, aliased) {
+        (Some(reference), Some(aliased)) => {
+            options.reference = reference;
+            options.aliased = aliased;
+            Ok(options)
+        }
+        _ => Err(Error::from_string(format!(
+            "quality needs --reference and --aliased\n\n{}",
+            USAGE
+        ))),
+    }
+}
+
+fn parse_lut<I: Iterator<Item = String>>(mut args: I) -> Result<LutOptions, Error> {
+    let mut options = LutOptions::default();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| Error::from_string(format!("Missing value for {}", arg)))
+        };
+        match arg.as_str() {
+            "--size" => {
+                let size = value()?;
+                options.size = size
+                    .parse()
+                    .map_err(|_| Error::from_string(format!("Invalid lookup table size {}", size)))?;
+            }
+            "--output" => options.output = PathBuf::from(value()?),
+            _ => {
+                return Err(Error::from_string(format!(
+                    "Unknown argument {}\n\n{}",
+                    arg, USAGE
+                )))
+            }
+        }
+    }
+    Ok(options)
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32), Error> {
+    let invalid = || Error::from_string(format!("Invalid size {}, expected e.g. 1280x720", value));
+    let mut parts = value.splitn(2, 'x');
+    let width = parts.next().and_then(|w| w.parse::<u32>().ok());
+    let height = parts.next().and_then(|h| h.parse::<u32>().ok());
+    match (width, height) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+fn parse_count(value: &str) -> Result<u64, Error> {
+    value
+        .parse()
+        .map_err(|_| Error::from_string(format!("Invalid frame count {}", value)))
+}
+
+fn parse_anti_aliasing(value: &str) -> Result<AntiAliasing, Error> {
+    let value = value.to_lowercase();
+    if value.ends_with("+sharpen") {
+        let preset = &value[..value.len() - "+sharpen".len()];
+        return match parse_anti_aliasing(preset)? {
+            AntiAliasing::Fxaa(quality) => Ok(AntiAliasing::FxaaSharpen(quality)),
+            _ => Err(Error::from_string(format!("Only fxaa presets can be sharpened, not {}", preset))),
+        };
+    }
+    match value.as_str() {
+        "off" => Ok(AntiAliasing::Off),
+        "low" => Ok(AntiAliasing::Fxaa(FxaaQuality::Low)),
+        "medium" => Ok(AntiAliasing::Fxaa(FxaaQuality::Medium)),
+        "high" => Ok(AntiAliasing::Fxaa(FxaaQuality::High)),
+        "ultra" => Ok(AntiAliasing::Fxaa(FxaaQuality::Ultra)),
+        "ssaa2" => Ok(AntiAliasing::Ssaa(2)),
+        "ssaa3" => Ok(AntiAliasing::Ssaa(3)),
+        "ssaa4" => Ok(AntiAliasing::Ssaa(4)),
+        _ => Err(Error::from_string(format!(
+            "Invalid anti-aliasing mode {}, expected off, low, medium, high, ultra, <preset>+sharpen, ssaa2, ssaa3 or ssaa4",
+            value
+        ))),
+    }
+}
+
+fn parse_debug_view(value: &str) -> Result<DebugView, Error> {
+    match value.to_lowercase().as_str() {
+        "off" => Ok(DebugView::Off),
+        "luma" => Ok(DebugView::Luma),
+        "edges" => Ok(DebugView::Edges),
+        "mask" => Ok(DebugView::Mask),
+        _ => Err(Error::from_string(format!(
+            "Invalid debug view {}, expected off, luma, edges or mask",
+            value
+        ))),
+    }
+}
@@ -0,0 +1,417 @@
+// color grading
+// a full-screen pass looking the color up in a 3D table, loaded as a texture asset from a .cube file
+// or a strip png, and the identity table artists start grading from
+
+use amethyst::{
+    assets::{AssetStorage, Format, Handle, Loader},
+    ecs::{World, WorldExt},
+    log,
+    renderer::{
+        rendy::{
+            hal::{
+                self,
+                image::{Filter, WrapMode},
+            },
+            texture::TextureBuilder,
+        },
+        types::TextureData,
+        Texture,
+    },
+    Error,
+};
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, FullscreenTexture, PassInfo};
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/color_grade.frag.spv");
+
+// tables outside this size range are rejected, 65 is the largest common export
+const MIN_SIZE: u32 = 2;
+const MAX_SIZE: u32 = 65;
+
+/// Color grading settings, loaded from `config/color_grade.ron`. The pass itself is enabled in
+/// the post-processing chain.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ColorGradeSettings {
+    /// Blend between the original (0.0) and the graded (1.0) color.
+    pub strength: f32,
+    /// `.cube` file or strip png relative to the assets directory, the identity table without one.
+    pub lut: Option<String>,
+    /// Reload assets when their file changes.
+    pub hot_reload: bool,
+}
+
+impl Default for ColorGradeSettings {
+    fn default() -> Self {
+        ColorGradeSettings {
+            strength: 1.0,
+            lut: None,
+            hot_reload: true,
+        }
+    }
+}
+
+/// Resource holding the texture of the loaded table.
+#[derive(Clone, Debug, Default)]
+pub struct ColorGradeLut {
+    pub handle: Option<Handle<Texture>>,
+}
+
+/// Start loading the table set in the settings, picking the format from the extension.
+pub fn load_lut(world: &mut World) {
+    let path = world.try_fetch::<ColorGradeSettings>().and_then(|settings| settings.lut.clone());
+    let handle = path.map(|path| {
+        log::info!("Loading color grading table {}", path);
+        let loader = world.read_resource::<Loader>();
+        let storage = world.read_resource::<AssetStorage<Texture>>();
+        if path.to_lowercase().ends_with(".cube") {
+            loader.load(path, CubeFormat, (), &storage)
+        } else {
+            loader.load(path, StripFormat, (), &storage)
+        }
+    });
+    world.insert(ColorGradeLut { handle });
+}
+
+/// Color grading pass writing an image of `target_size`. The identity table is bound until the
+/// configured one is loaded, a reloaded table is picked up on the next frame.
+pub fn pass(target_size: (u32, u32)) -> FullscreenPassDesc<ColorGradeArgs> {
+    FullscreenPassDesc::new("Color grading", FRAGMENT_SPIRV, target_size, uniform_args)
+        .with_input(FullscreenInput::linear())
+        .with_texture(FullscreenTexture {
+            handle: |world| world.try_fetch::<ColorGradeLut>().and_then(|lut| lut.handle.clone()),
+            fallback: || Lut::identity(MIN_SIZE).texture_builder(),
+        })
+        .with_uniform_layout(crate::uniform_layout!(ColorGradeArgs { source_size, target_size, strength }))
+}
+
+fn uniform_args(world: &World, info: &PassInfo) -> ColorGradeArgs {
+    let strength = world
+        .try_fetch::<ColorGradeSettings>()
+        .map_or(1.0, |settings| settings.strength);
+    let (source_size, target_size) = info.sizes();
+    ColorGradeArgs {
+        source_size,
+        target_size,
+        strength,
+    }
+}
+
+/// layout(std140, set = 0, binding = 0) uniform ColorGradeArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform float strength;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct ColorGradeArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    pub strength: float,
+}
+
+/// 3D lookup table of `size` entries along each axis, red varying fastest and blue slowest, in the
+/// order of a .cube file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lut {
+    pub size: u32,
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl Lut {
+    pub fn identity(size: u32) -> Self {
+        let step = |i: u32| i as f32 / (size - 1) as f32;
+        let mut entries = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    entries.push([step(r), step(g), step(b)]);
+                }
+            }
+        }
+        Lut { size, entries }
+    }
+
+    /// Parse an Adobe/Resolve .cube file. Only 3D tables over the default 0 to 1 domain are supported.
+    pub fn parse_cube(text: &str) -> Result<Self, Error> {
+        let mut size = None;
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || Error::from_string(format!("Invalid .cube line {}: {}", number + 1, line));
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let numbers = |words: std::str::SplitWhitespace<'_>| {
+                words.map(|word| word.parse::<f32>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())
+            };
+            match keyword {
+                "TITLE" => (),
+                "LUT_3D_SIZE" => {
+                    let value = words.next().and_then(|word| word.parse::<u32>().ok()).ok_or_else(invalid)?;
+                    size = Some(value);
+                }
+                "LUT_1D_SIZE" => return Err(Error::from_string("1D .cube tables aren't supported")),
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if numbers(words)?.iter().any(|value| *value != expected) {
+                        return Err(Error::from_string("Only the 0 to 1 .cube domain is supported"));
+                    }
+                }
+                _ => match numbers(line.split_whitespace())?.as_slice() {
+                    [r, g, b] => entries.push([*r, *g, *b]),
+                    _ => return Err(invalid()),
+                },
+            }
+        }
+
+        let size = size.ok_or_else(|| Error::from_string("The .cube file has no LUT_3D_SIZE"))?;
+        check_size(size)?;
+        if entries.len() != (size * size * size) as usize {
+            return Err(Error::from_string(format!(
+                "The .cube file has {} entries, expected {} for size {}",
+                entries.len(),
+                size * size * size,
+                size
+            )));
+        }
+        Ok(Lut { size, entries })
+    }
+
+    /// Read a strip of `size` slices of `size` by `size` texels side by side, red increasing to the
+    /// right within a slice, green downwards and blue from slice to slice.
+    pub fn from_strip(image: &image::RgbaImage) -> Result<Self, Error> {
+        let (width, height) = image.dimensions();
+        let size = height;
+        if width != size * size {
+            return Err(Error::from_string(format!(
+                "A {}x{} strip isn't a lookup table, expected {}x{}",
+                width,
+                height,
+                size * size,
+                size
+            )));
+        }
+        check_size(size)?;
+        let mut entries = Vec::with_capacity((size * size * size) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    let pixel = image.get_pixel(b * size + r, g);
+                    entries.push([
+                        pixel[0] as f32 / 255.0,
+                        pixel[1] as f32 / 255.0,
+                        pixel[2] as f32 / 255.0,
+                    ]);
+                }
+            }
+        }
+        Ok(Lut { size, entries })
+    }
+
+    /// The table as a strip image, the layout `from_strip` reads.
+    pub fn to_strip(&self) -> image::RgbaImage {
+        let size = self.size;
+        image::RgbaImage::from_fn(size * size, size, |x, y| {
+            let (b, r) = (x / size, x % size);
+            let [red, green, blue] = self.entries[(r + y * size + b * size * size) as usize];
+            image::Rgba([unorm8(red), unorm8(green), unorm8(blue), 255])
+        })
+    }
+
+    /// The table in .cube format.
+    pub fn to_cube(&self, title: &str) -> String {
+        let mut text = format!("TITLE \"{}\"\nLUT_3D_SIZE {}\n", title, self.size);
+        for [r, g, b] in &self.entries {
+            text.push_str(&format!("{:.6} {:.6} {:.6}\n", r, g, b));
+        }
+        text
+    }
+
+    /// 3D texture sampled with trilinear filtering between the entries.
+    pub fn texture_builder(&self) -> TextureBuilder<'static> {
+        let data: Vec<u8> = self
+            .entries
+            .iter()
+            .flat_map(|[r, g, b]| vec![unorm8(*r), unorm8(*g), unorm8(*b), 255])
+            .collect();
+        TextureBuilder::new()
+            .with_kind(hal::image::Kind::D3(self.size, self.size, self.size))
+            .with_view_kind(hal::image::ViewKind::D3)
+            .with_data_width(self.size)
+            .with_data_height(self.size)
+            .with_sampler_info(hal::image::SamplerInfo::new(Filter::Linear, WrapMode::Clamp))
+            .with_raw_data(data, hal::format::Format::Rgba8Unorm)
+    }
+}
+
+fn check_size(size: u32) -> Result<(), Error> {
+    if size < MIN_SIZE || size > MAX_SIZE {
+        return Err(Error::from_string(format!(
+            "Lookup table size {} is outside {} to {}",
+            size, MIN_SIZE, MAX_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn unorm8(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+/// Loads an Adobe/Resolve .cube file into a 3D texture.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CubeFormat;
+
+impl Format<TextureData> for CubeFormat {
+    fn name(&self) -> &'static str {
+        "CUBE"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let text = String::from_utf8(bytes).map_err(|_| Error::from_string("The .cube file isn't text"))?;
+        Ok(Lut::parse_cube(&text)?.texture_builder().into())
+    }
+}
+
+/// Loads a strip image, see `Lut::from_strip`, into a 3D texture.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StripFormat;
+
+impl Format<TextureData> for StripFormat {
+    fn name(&self) -> &'static str {
+        "LUT_STRIP"
+    }
+
+    fn import_simple(&self, bytes: Vec<u8>) -> Result<TextureData, Error> {
+        let image = image::load_from_memory(&bytes)
+            .map_err(|err| Error::from_string(format!("Failed to decode the strip: {}", err)))?
+            .to_rgba();
+        Ok(Lut::from_strip(&image)?.texture_builder().into())
+    }
+}
+
+/// Options of the `lut` command.
+#[derive(Clone, Debug)]
+pub struct LutOptions {
+    pub size: u32,
+    /// Written as a .cube file when the extension is .cube, as a strip png otherwise.
+    pub output: PathBuf,
+}
+
+impl Default for LutOptions {
+    fn default() -> Self {
+        LutOptions {
+            size: 32,
+            output: PathBuf::from("identity.png"),
+        }
+    }
+}
+
+/// Write the identity table, to be graded in an external tool. Pasted into a screenshot as a strip,
+/// it receives the same adjustments as the image.
+pub fn write_identity(options: &LutOptions) -> Result<(), Error> {
+    check_size(options.size)?;
+    let lut = Lut::identity(options.size);
+    let path = &options.output;
+    let cube = path
+        .extension()
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("cube"));
+    let result = if cube {
+        std::fs::write(path, lut.to_cube("Identity")).map_err(|err| err.to_string())
+    } else {
+        lut.to_strip().save(path).map_err(|err| err.to_string())
+    };
+    result.map_err(|err| Error::from_string(format!("Failed to write {:?}: {}", path, err)))?;
+    log::info!("Identity lookup table of size {} written to {:?}", options.size, path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lut;
+
+    fn assert_close(a: &Lut, b: &Lut, tolerance: f32) {
+        assert_eq!(a.size, b.size);
+        assert_eq!(a.entries.len(), b.entries.len());
+        for (index, (a, b)) in a.entries.iter().zip(&b.entries).enumerate() {
+            for c in 0..3 {
+                assert!((a[c] - b[c]).abs() <= tolerance, "entry {}: {:?} != {:?}", index, a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn identity_round_trips_through_cube() {
+        for size in &[2, 17, 33] {
+            let lut = Lut::identity(*size);
+            let parsed = Lut::parse_cube(&lut.to_cube("Identity")).unwrap();
+            assert_close(&parsed, &lut, 1e-6);
+        }
+    }
+
+    #[test]
+    fn identity_round_trips_through_strip() {
+        for size in &[2, 16, 17] {
+            let lut = Lut::identity(*size);
+            let strip = lut.to_strip();
+            assert_eq!(strip.dimensions(), (size * size, *size));
+            // rounded to 8 bits per channel
+            assert_close(&Lut::from_strip(&strip).unwrap(), &lut, 0.5 / 255.0 + 1e-6);
+        }
+    }
+
+    #[test]
+    fn cube_entries_are_red_fastest() {
+        let text = "LUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+        assert_eq!(Lut::parse_cube(text).unwrap(), Lut::identity(2));
+    }
+
+    #[test]
+    fn wrong_entry_count_is_rejected() {
+        let mut text = Lut::identity(3).to_cube("Short");
+        // drop the last entry
+        let end = text.trim_end().rfind('\n').unwrap();
+        text.truncate(end + 1);
+        let err = Lut::parse_cube(&text).unwrap_err();
+        assert!(err.to_string().contains("26 entries, expected 27"), "{}", err);
+
+        let text = format!("{}0 0 0\n", Lut::identity(2).to_cube("Long"));
+        assert!(Lut::parse_cube(&text).is_err());
+    }
+
+    #[test]
+    fn one_dimensional_tables_are_rejected() {
+        let err = Lut::parse_cube("LUT_1D_SIZE 2\n0 0 0\n1 1 1\n").unwrap_err();
+        assert!(err.to_string().contains("1D"), "{}", err);
+    }
+
+    #[test]
+    fn only_the_default_domain_is_accepted() {
+        let identity = Lut::identity(2).to_cube("Identity");
+        let with_domain = |min: &str, max: &str| format!("DOMAIN_MIN {}\nDOMAIN_MAX {}\n{}", min, max, identity);
+        assert!(Lut::parse_cube(&with_domain("0 0 0", "1 1 1")).is_ok());
+        assert!(Lut::parse_cube(&with_domain("0 0 0", "2 2 2")).is_err());
+        assert!(Lut::parse_cube(&with_domain("-0.5 0 0", "1 1 1")).is_err());
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        assert!(Lut::parse_cube("0 0 0\n").is_err());
+        assert!(Lut::parse_cube("LUT_3D_SIZE 1\n0 0 0\n").is_err());
+        assert!(Lut::parse_cube("LUT_3D_SIZE 66\n").is_err());
+        assert!(Lut::from_strip(&image::RgbaImage::new(8, 4)).is_err());
+        assert!(Lut::from_strip(&image::RgbaImage::new(1, 1)).is_err());
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        let err = Lut::parse_cube("LUT_3D_SIZE 2\n0 0\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+        assert!(Lut::parse_cube("LUT_3D_SIZE two\n").is_err());
+    }
+}
@@ -0,0 +1,247 @@
+// dithering and film grain
+// the last full-screen pass, adding noise before the 8-bit output so dark gradients don't band
+
+use amethyst::{
+    core::Time,
+    ecs::World,
+    renderer::rendy::{
+        hal::{
+            self,
+            image::{Filter, WrapMode},
+        },
+        texture::TextureBuilder,
+    },
+};
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, FullscreenTexture, PassInfo};
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+
+const FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/dither.frag.spv");
+
+// side of the tiled blue noise texture
+const BLUE_NOISE_SIZE: usize = 64;
+
+// the blue noise tile, precomputed by `blue_noise` in the tests as generating it takes a while
+const BLUE_NOISE: &[u8] = include_bytes!("../assets/texture/blue_noise.bin");
+
+/// Noise added to break up banding, one code value of the output in amplitude.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DitherMode {
+    Off,
+    /// White noise with a triangular distribution, which hides the quantization error completely.
+    Triangular,
+    /// Tiled blue noise, less visible than white noise at the same amplitude.
+    BlueNoise,
+}
+
+impl Default for DitherMode {
+    fn default() -> Self {
+        DitherMode::BlueNoise
+    }
+}
+
+impl DitherMode {
+    // value of the matching DITHER_* define in dither.frag
+    fn shader_value(self) -> u32 {
+        match self {
+            DitherMode::Off => 0,
+            DitherMode::Triangular => 1,
+            DitherMode::BlueNoise => 2,
+        }
+    }
+}
+
+/// Dithering settings, loaded from `config/dither.ron`. The pass itself is enabled in the
+/// post-processing chain.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DitherSettings {
+    pub mode: DitherMode,
+    /// Strength of the film grain, 0.0 disables it and 0.05 is already clearly visible.
+    pub grain: f32,
+    /// Change the noise every frame. Still noise can look like a dirty screen, animated noise
+    /// averages out over frames.
+    pub animated: bool,
+}
+
+impl Default for DitherSettings {
+    fn default() -> Self {
+        DitherSettings {
+            mode: DitherMode::default(),
+            grain: 0.0,
+            animated: true,
+        }
+    }
+}
+
+/// Dithering pass writing an image of `target_size`, meant to be the last of the chain.
+pub fn pass(target_size: (u32, u32)) -> FullscreenPassDesc<DitherArgs> {
+    FullscreenPassDesc::new("Dither", FRAGMENT_SPIRV, target_size, uniform_args)
+        .with_input(FullscreenInput::linear())
+        .with_texture(FullscreenTexture {
+            handle: |_| None,
+            fallback: blue_noise_texture,
+        })
+        .with_uniform_layout(crate::uniform_layout!(DitherArgs {
+            source_size, target_size, mode, grain, animated, frame,
+        }))
+}
+
+fn uniform_args(world: &World, info: &PassInfo) -> DitherArgs {
+    let settings = world
+        .try_fetch::<DitherSettings>()
+        .map_or_else(DitherSettings::default, |settings| settings.clone());
+    let frame = world.try_fetch::<Time>().map_or(0, |time| time.frame_number());
+    let (source_size, target_size) = info.sizes();
+    DitherArgs {
+        source_size,
+        target_size,
+        mode: settings.mode.shader_value().into(),
+        grain: settings.grain.max(0.0),
+        animated: settings.animated.into(),
+        frame: (frame as u32).into(),
+    }
+}
+
+/// layout(std140, set = 0, binding = 0) uniform DitherArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform uint mode;
+///    uniform float grain;
+///    uniform bool animated;
+///    uniform uint frame;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct DitherArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    // DitherMode as integer
+    pub mode: uint,
+    pub grain: float,
+    pub animated: boolean,
+    // frame number, seeding the animated noise
+    pub frame: uint,
+}
+
+// tiled blue noise, sampled texel by texel
+fn blue_noise_texture() -> TextureBuilder<'static> {
+    let size = BLUE_NOISE_SIZE as u32;
+    let data: Vec<u8> = BLUE_NOISE.iter().flat_map(|value| vec![*value, *value, *value, 255]).collect();
+    TextureBuilder::new()
+        .with_kind(hal::image::Kind::D2(size, size, 1, 1))
+        .with_view_kind(hal::image::ViewKind::D2)
+        .with_data_width(size)
+        .with_data_height(size)
+        .with_sampler_info(hal::image::SamplerInfo::new(Filter::Nearest, WrapMode::Tile))
+        .with_raw_data(data, hal::format::Format::Rgba8Unorm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // void-and-cluster blue noise (Ulichney 1993), each pixel's rank spread over 0 to 255
+    fn blue_noise(size: usize) -> Vec<u8> {
+        let n = size * size;
+
+        // gaussian energy a point adds around it, wrapping around the tile
+        let sigma = 1.5f32;
+        let kernel: Vec<f32> = (0..n)
+            .map(|i| {
+                let (x, y) = (i % size, i / size);
+                let dx = x.min(size - x) as f32;
+                let dy = y.min(size - y) as f32;
+                (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()
+            })
+            .collect();
+        let update = |energy: &mut [f32], point: usize, sign: f32| {
+            let (px, py) = (point % size, point / size);
+            for y in 0..size {
+                for x in 0..size {
+                    let dx = (x + size - px) % size;
+                    let dy = (y + size - py) % size;
+                    energy[x + y * size] += sign * kernel[dx + dy * size];
+                }
+            }
+        };
+        // the tightest cluster is the point with the highest energy, the largest void the empty pixel
+        // with the lowest
+        let tightest_cluster = |energy: &[f32], points: &[bool]| {
+            (0..n)
+                .filter(|&i| points[i])
+                .max_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+                .unwrap()
+        };
+        let largest_void = |energy: &[f32], points: &[bool]| {
+            (0..n)
+                .filter(|&i| !points[i])
+                .min_by(|&a, &b| energy[a].partial_cmp(&energy[b]).unwrap())
+                .unwrap()
+        };
+
+        // initial pattern of a tenth of the pixels, picked by a fixed lcg so the noise is the same
+        // on every run
+        let mut points = vec![false; n];
+        let mut energy = vec![0.0f32; n];
+        let initial = n / 10;
+        let mut seed = 0x2545_f491u32;
+        let mut count = 0;
+        while count < initial {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let point = (seed >> 8) as usize % n;
+            if !points[point] {
+                points[point] = true;
+                update(&mut energy, point, 1.0);
+                count += 1;
+            }
+        }
+
+        // spread it evenly, moving the tightest cluster into the largest void until it stays put
+        for _ in 0..n {
+            let cluster = tightest_cluster(&energy, &points);
+            points[cluster] = false;
+            update(&mut energy, cluster, -1.0);
+            let void = largest_void(&energy, &points);
+            points[void] = true;
+            update(&mut energy, void, 1.0);
+            if void == cluster {
+                break;
+            }
+        }
+
+        // rank the initial points by removing the tightest clusters, then the rest by filling the
+        // largest voids
+        let mut rank = vec![0; n];
+        let (mut removed_points, mut removed_energy) = (points.clone(), energy.clone());
+        for r in (0..initial).rev() {
+            let cluster = tightest_cluster(&removed_energy, &removed_points);
+            removed_points[cluster] = false;
+            update(&mut removed_energy, cluster, -1.0);
+            rank[cluster] = r;
+        }
+        for r in initial..n {
+            let void = largest_void(&energy, &points);
+            points[void] = true;
+            update(&mut energy, void, 1.0);
+            rank[void] = r;
+        }
+
+        rank.iter().map(|r| (r * 256 / n) as u8).collect()
+    }
+
+    #[test]
+    fn shipped_blue_noise_matches_the_generator() {
+        // regenerate assets/texture/blue_noise.bin from this when changing the generator
+        assert!(blue_noise(BLUE_NOISE_SIZE) == BLUE_NOISE, "blue_noise.bin is out of date");
+    }
+
+    #[test]
+    fn blue_noise_covers_every_value_evenly() {
+        let mut histogram = [0; 256];
+        for value in BLUE_NOISE {
+            histogram[*value as usize] += 1;
+        }
+        assert!(histogram.iter().all(|count| *count == BLUE_NOISE_SIZE * BLUE_NOISE_SIZE / 256));
+    }
+}
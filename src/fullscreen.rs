@@ -0,0 +1,802 @@
+// generic full-screen pass
+// everything a post effect needs besides its fragment shader and uniform struct: the per-frame
+// uniform buffer ring, samplers and views of the input images, the descriptor sets, an optional
+// texture asset, an optional storage buffer read back by the cpu and the full-screen quad
+// based on tonepass pipeline from pbr-rendy: https://github.com/termhn/rendy-pbr/blob/master/src/node/pbr/tonemap.rs
+
+use amethyst::{
+    assets::{AssetStorage, Handle},
+    ecs::World,
+    log,
+    renderer::{
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        types::Backend,
+        util, Texture,
+    },
+};
+use rendy::{
+    command::{QueueId, RenderPassEncoder},
+    factory::{Factory, ImageState},
+    graph::{
+        render::{PrepareResult, RenderGroup, RenderGroupDesc},
+        GraphContext, ImageAccess, NodeBuffer, NodeImage,
+    },
+    hal::{
+        self,
+        device::Device,
+        format::Format,
+        image::Filter::{Linear, Nearest},
+        image::WrapMode,
+        pso::DescriptorPool,
+        pso::DescriptorType,
+        pso::ShaderStageFlags,
+    },
+    memory,
+    mesh::{AsVertex, VertexFormat},
+    resource::{
+        self, Buffer, BufferInfo, DescriptorSetLayout, Escape, Handle as RendyHandle, ImageView,
+        ImageViewInfo, Sampler, SamplerInfo,
+    },
+    shader::{Shader, SpirvShader},
+    texture::{Texture as RendyTexture, TextureBuilder},
+};
+use crate::reflect::{SetDescription, UniformLayout};
+use failure::ResultExt;
+use glsl_layout::*;
+use std::borrow::Cow;
+use std::fmt;
+use std::mem::size_of;
+use std::sync::Arc;
+
+const VERTEX_SPIRV: &[u8] = include_bytes!("../assets/shader/fullscreen.vert.spv");
+
+// frames in flight the uniforms and descriptor sets are duplicated for
+const FRAMES: usize = 3;
+
+/// Vertex Arguments to pass into shader.
+/// layout(location = 0) in vec2 position;
+/// layout(location = 1) in vec2 tex_coord;
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
+#[repr(C, align(4))]
+pub struct FullscreenVertexArgs {
+    pub position: vec2,
+    pub tex_coord: vec2,
+}
+
+/// Required to send data into the shader.
+/// These names must match the shader.
+impl AsVertex for FullscreenVertexArgs {
+    fn vertex() -> VertexFormat {
+        VertexFormat::new((
+            (Format::Rg32Sfloat, "position"),
+            (Format::Rg32Sfloat, "tex_coord"),
+        ))
+    }
+}
+
+/// An image the fragment shader samples, bound after the uniform buffer in the order added.
+#[derive(Clone, Copy, Debug)]
+pub struct FullscreenInput {
+    /// Layout the image is read in.
+    pub layout: hal::image::Layout,
+    /// Sampled with bilinear filtering instead of reading the nearest texel.
+    pub linear: bool,
+}
+
+impl FullscreenInput {
+    /// Color image sampled with bilinear filtering.
+    pub fn linear() -> Self {
+        FullscreenInput {
+            layout: hal::image::Layout::ShaderReadOnlyOptimal,
+            linear: true,
+        }
+    }
+
+    /// Image read texel by texel.
+    pub fn nearest() -> Self {
+        FullscreenInput {
+            layout: hal::image::Layout::ShaderReadOnlyOptimal,
+            linear: false,
+        }
+    }
+
+    /// Depth buffer, read texel by texel.
+    pub fn depth() -> Self {
+        FullscreenInput {
+            layout: hal::image::Layout::DepthStencilReadOnlyOptimal,
+            linear: false,
+        }
+    }
+}
+
+/// Texture asset sampled by the shader, bound after the input images. `fallback` is uploaded with
+/// the pass and bound while `handle` returns nothing or its asset isn't loaded yet, a reloaded asset
+/// is bound from the next frame on.
+#[derive(Clone, Copy, Debug)]
+pub struct FullscreenTexture {
+    pub handle: fn(&World) -> Option<Handle<Texture>>,
+    pub fallback: fn() -> TextureBuilder<'static>,
+}
+
+/// What the uniform function knows about the pass besides the world.
+#[derive(Clone, Copy, Debug)]
+pub struct PassInfo {
+    /// Size of the first input image in texels.
+    pub source_size: (u32, u32),
+    /// Size of the image written to in pixels.
+    pub target_size: (u32, u32),
+}
+
+impl PassInfo {
+    /// Source and target size as shader vectors, the `source_size` and `target_size` uniforms.
+    pub fn sizes(&self) -> (vec2, vec2) {
+        let size = |(width, height): (u32, u32)| -> vec2 { [width as f32, height as f32].into() };
+        (size(self.source_size), size(self.target_size))
+    }
+}
+
+/// Storage buffer the fragment shader writes with atomics, bound after the input images. Each frame's
+/// copy is handed to `read` once that frame is complete and then cleared. Writing it needs
+/// `Features::FRAGMENT_STORES_AND_ATOMICS`, building the pass fails on devices without.
+#[derive(Clone, Copy, Debug)]
+pub struct FullscreenStorage {
+    pub size: u64,
+    pub read: fn(&World, &[u8]),
+}
+
+/// Fills the uniform struct of a frame.
+pub type UniformFn<U> = Arc<dyn Fn(&World, &PassInfo) -> U + Send + Sync>;
+
+/// Full-screen pass drawing `fragment` into the color attachment, with the uniform `U` at binding 0.
+pub struct FullscreenPassDesc<U> {
+    name: Cow<'static, str>,
+    fragment: Cow<'static, [u8]>,
+    target_size: (u32, u32),
+    inputs: Vec<FullscreenInput>,
+    texture: Option<FullscreenTexture>,
+    storage: Option<FullscreenStorage>,
+    uniform: UniformFn<U>,
+    uniform_layout: Option<UniformLayout>,
+}
+
+impl<U> fmt::Debug for FullscreenPassDesc<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FullscreenPassDesc")
+            .field("name", &self.name)
+            .field("target_size", &self.target_size)
+            .field("inputs", &self.inputs)
+            .field("texture", &self.texture)
+            .field("storage", &self.storage)
+            .finish()
+    }
+}
+
+impl<U> FullscreenPassDesc<U>
+where
+    U: AsStd140 + 'static,
+    U::Std140: Copy,
+{
+    /// Pass named `name` in errors, rendering into an image of `target_size`.
+    pub fn new<N, S, F>(name: N, fragment: S, target_size: (u32, u32), uniform: F) -> Self
+    where
+        N: Into<Cow<'static, str>>,
+        S: Into<Cow<'static, [u8]>>,
+        F: Fn(&World, &PassInfo) -> U + Send + Sync + 'static,
+    {
+        FullscreenPassDesc {
+            name: name.into(),
+            fragment: fragment.into(),
+            target_size,
+            inputs: Vec::new(),
+            texture: None,
+            storage: None,
+            uniform: Arc::new(uniform),
+            uniform_layout: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn target_size(&self) -> (u32, u32) {
+        self.target_size
+    }
+
+    pub fn with_input(mut self, input: FullscreenInput) -> Self {
+        self.inputs.push(input);
+        self
+    }
+
+    pub fn with_texture(mut self, texture: FullscreenTexture) -> Self {
+        self.texture = Some(texture);
+        self
+    }
+
+    pub fn with_storage(mut self, storage: FullscreenStorage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Layout of `U` to check against the uniform block of the shader, see `uniform_layout!`.
+    pub fn with_uniform_layout(mut self, layout: UniformLayout) -> Self {
+        self.uniform_layout = Some(layout);
+        self
+    }
+
+    fn set(&self) -> SetDescription {
+        let mut bindings = vec![DescriptorType::UniformBuffer];
+        bindings.extend(self.inputs.iter().map(|_| DescriptorType::CombinedImageSampler));
+        if self.texture.is_some() {
+            bindings.push(DescriptorType::CombinedImageSampler);
+        }
+        if self.storage.is_some() {
+            bindings.push(DescriptorType::StorageBuffer);
+        }
+        SetDescription::new(bindings)
+    }
+
+    // vertex and fragment shader, failing on SPIR-V that doesn't load
+    pub(crate) fn shaders(&self) -> Result<(SpirvShader, SpirvShader), failure::Error> {
+        let vertex = SpirvShader::from_bytes(VERTEX_SPIRV, ShaderStageFlags::VERTEX, "main")
+            .context("Failed to load the full-screen vertex shader")?;
+        let fragment = SpirvShader::from_bytes(&self.fragment, ShaderStageFlags::FRAGMENT, "main")
+            .with_context(|_| format!("Failed to load the {} fragment shader", self.name))?;
+        Ok((vertex, fragment))
+    }
+
+    /// Check that the shaders load and match the uniform struct, descriptor set and vertex format,
+    /// before the pass is added to a graph.
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        let name = &self.name;
+        self.shaders()?;
+        if let Some(ref layout) = self.uniform_layout {
+            crate::reflect::check_uniform_block(&self.fragment, 0, 0, layout)
+                .with_context(|_| format!("{} pass", name))?;
+        }
+        self.set()
+            .check_shader(&self.fragment, 0)
+            .with_context(|_| format!("{} pass", name))?;
+        crate::reflect::check_vertex_input(VERTEX_SPIRV, &FullscreenVertexArgs::vertex())?;
+        Ok(())
+    }
+}
+
+pub struct FullscreenPass<B: hal::Backend, U> {
+    // missing when the pipeline or its resources failed to build, the pass then draws nothing until the graph is
+    // rebuilt without it
+    resources: Option<Resources<B>>,
+    uniform: UniformFn<U>,
+    texture: Option<FullscreenTexture>,
+    storage: Option<FullscreenStorage>,
+}
+
+impl<B: hal::Backend, U> fmt::Debug for FullscreenPass<B, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FullscreenPass")
+            .field("resources", &self.resources)
+            .field("texture", &self.texture)
+            .field("storage", &self.storage)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+struct Resources<B: hal::Backend> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    set_layout: RendyHandle<DescriptorSetLayout<B>>,
+    buffer: Escape<Buffer<B>>,
+    uniform_frame_size: u64,
+    sets: Vec<B::DescriptorSet>,
+    descriptor_pool: B::DescriptorPool,
+    samplers: Vec<Escape<Sampler<B>>>,
+    views: Vec<Escape<ImageView<B>>>,
+    // bound in place of the texture asset until it is loaded
+    fallback_texture: Option<RendyTexture<B>>,
+    texture_binding: u32,
+    storage_buffer: Option<Escape<Buffer<B>>>,
+    storage_frame_size: u64,
+    vertex_buffer: Escape<Buffer<B>>,
+    info: PassInfo,
+}
+
+impl<B, U> RenderGroupDesc<B, World> for FullscreenPassDesc<U>
+where
+    B: Backend,
+    U: AsStd140 + 'static,
+    U::Std140: Copy,
+{
+    fn images(&self) -> Vec<ImageAccess> {
+        self.inputs
+            .iter()
+            .map(|input| ImageAccess {
+                access: hal::image::Access::SHADER_READ,
+                usage: hal::image::Usage::SAMPLED,
+                layout: input.layout,
+                stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
+            })
+            .collect()
+    }
+
+    fn depth(&self) -> bool {
+        false
+    }
+
+    fn build(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        // a failure only disables the pass, the graph is then rebuilt presenting the image without it
+        let resources = self
+            .build_resources(
+                ctx,
+                factory,
+                queue,
+                (framebuffer_width, framebuffer_height),
+                subpass,
+                images,
+            )
+            .map_err(|err| crate::graph::PostProcessFallback::report(world, &self.name, &err))
+            .ok();
+        Ok(Box::new(FullscreenPass::<B, U> {
+            resources,
+            uniform: self.uniform,
+            texture: self.texture,
+            storage: self.storage,
+        }))
+    }
+}
+
+impl<U> FullscreenPassDesc<U>
+where
+    U: AsStd140 + 'static,
+    U::Std140: Copy,
+{
+    fn build_resources<B: Backend>(
+        &self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        queue: QueueId,
+        framebuffer_size: (u32, u32),
+        subpass: hal::pass::Subpass<'_, B>,
+        images: Vec<NodeImage>,
+    ) -> Result<Resources<B>, failure::Error> {
+        let name = &self.name;
+        if images.len() != self.inputs.len() {
+            failure::bail!("{} expects {} images, got {}", name, self.inputs.len(), images.len());
+        }
+
+        let set = self.set();
+        let set_layout: RendyHandle<DescriptorSetLayout<B>> = factory
+            .create_descriptor_set_layout(set.layout().bindings)
+            .with_context(|_| format!("Failed to create the {} descriptor set layout", name))?
+            .into();
+
+        let limits = hal::adapter::PhysicalDevice::limits(factory.physical());
+        let align = limits.min_uniform_buffer_offset_alignment;
+        let uniform_size = size_of::<U::Std140>() as u64;
+        let uniform_frame_size = ((uniform_size - 1) / align + 1) * align;
+
+        // storage is written with atomics from the fragment shader
+        if self.storage.is_some()
+            && !hal::adapter::PhysicalDevice::features(factory.physical())
+                .contains(hal::Features::FRAGMENT_STORES_AND_ATOMICS)
+        {
+            failure::bail!("The {} storage buffer needs fragment shader atomics, which this device lacks", name);
+        }
+        let storage_align = limits.min_storage_buffer_offset_alignment;
+        let storage_frame_size = self
+            .storage
+            .map_or(0, |storage| ((storage.size - 1) / storage_align + 1) * storage_align);
+
+        let mut samplers = Vec::with_capacity(self.inputs.len());
+        let mut views = Vec::with_capacity(self.inputs.len());
+        let mut source_size = self.target_size;
+        for (index, (input, node_image)) in self.inputs.iter().zip(&images).enumerate() {
+            let filter = if input.linear { Linear } else { Nearest };
+            samplers.push(
+                factory
+                    .create_sampler(SamplerInfo::new(filter, WrapMode::Clamp))
+                    .with_context(|_| format!("Failed to create a {} sampler", name))?,
+            );
+
+            let handle = ctx
+                .get_image(node_image.id)
+                .ok_or_else(|| failure::format_err!("{} input image {} missing", name, index))?;
+            // texel size comes from the image actually sampled, which may be scaled or lag behind
+            // the window during a resize
+            if index == 0 {
+                let extent = handle.kind().extent();
+                source_size = (extent.width, extent.height);
+            }
+            views.push(
+                factory
+                    .create_image_view(
+                        handle.clone(),
+                        ImageViewInfo {
+                            view_kind: resource::ViewKind::D2,
+                            // view with the format of the image, so an sRGB input is decoded before filtering
+                            format: handle.format(),
+                            swizzle: hal::format::Swizzle::NO,
+                            range: node_image.range.clone(),
+                        },
+                    )
+                    .with_context(|_| format!("Failed to create a {} image view", name))?,
+            );
+        }
+
+        let fallback_texture = match self.texture {
+            Some(texture) => Some(
+                (texture.fallback)()
+                    .build(
+                        ImageState {
+                            queue,
+                            stage: hal::pso::PipelineStage::FRAGMENT_SHADER,
+                            access: hal::image::Access::SHADER_READ,
+                            layout: hal::image::Layout::ShaderReadOnlyOptimal,
+                        },
+                        factory,
+                    )
+                    .with_context(|_| format!("Failed to upload the {} fallback texture", name))?,
+            ),
+            None => None,
+        };
+
+        let buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: uniform_frame_size * FRAMES as u64,
+                    usage: hal::buffer::Usage::UNIFORM,
+                },
+                memory::Dynamic,
+            )
+            .with_context(|_| format!("Failed to create the {} uniform buffer", name))?;
+
+        let storage_buffer = match self.storage {
+            Some(_) => {
+                let size = storage_frame_size * FRAMES as u64;
+                let mut storage_buffer = factory
+                    .create_buffer(
+                        BufferInfo {
+                            size,
+                            usage: hal::buffer::Usage::STORAGE,
+                        },
+                        memory::Dynamic,
+                    )
+                    .with_context(|_| format!("Failed to create the {} storage buffer", name))?;
+                unsafe {
+                    factory
+                        .upload_visible_buffer(&mut storage_buffer, 0, &vec![0u8; size as usize])
+                        .with_context(|_| format!("Failed to clear the {} storage buffer", name))?;
+                }
+                Some(storage_buffer)
+            }
+            None => None,
+        };
+
+        // create a static vertex buffer
+        let mut vertex_buffer = factory
+            .create_buffer(
+                BufferInfo {
+                    size: FullscreenVertexArgs::vertex().stride as u64 * 6,
+                    usage: hal::buffer::Usage::VERTEX,
+                },
+                memory::Dynamic,
+            )
+            .with_context(|_| format!("Failed to create the {} vertex buffer", name))?;
+        unsafe {
+            factory
+                .upload_visible_buffer(
+                    &mut vertex_buffer,
+                    0,
+                    &[
+                        FullscreenVertexArgs { position: [-1f32, 1f32].into(), tex_coord: [0f32, 1f32].into() },
+                        FullscreenVertexArgs { position: [1f32, -1f32].into(), tex_coord: [1f32, 0f32].into() },
+                        FullscreenVertexArgs { position: [-1f32, -1f32].into(), tex_coord: [0f32, 0f32].into() },
+                        FullscreenVertexArgs { position: [1f32, -1f32].into(), tex_coord: [1f32, 0f32].into() },
+                        FullscreenVertexArgs { position: [-1f32, 1f32].into(), tex_coord: [0f32, 1f32].into() },
+                        FullscreenVertexArgs { position: [1f32, 1f32].into(), tex_coord: [1f32, 1f32].into() },
+                    ],
+                )
+                .with_context(|_| format!("Failed to upload the {} vertex buffer", name))?;
+        }
+
+        // the pool and the pipeline are created last, so an error before doesn't leak them
+        let mut descriptor_pool = unsafe {
+            factory
+                .create_descriptor_pool(
+                    FRAMES,
+                    set.pool_ranges(FRAMES),
+                    hal::pso::DescriptorPoolCreateFlags::empty(),
+                )
+                .with_context(|_| format!("Failed to create the {} descriptor pool", name))?
+        };
+
+        let mut sets = Vec::with_capacity(FRAMES);
+        for index in 0..FRAMES {
+            let uniform_offset = uniform_frame_size * index as u64;
+            let mut descriptors = vec![hal::pso::Descriptor::Buffer(
+                buffer.raw(),
+                Some(uniform_offset)..Some(uniform_offset + uniform_size),
+            )];
+            for ((input, view), sampler) in self.inputs.iter().zip(&views).zip(&samplers) {
+                descriptors.push(hal::pso::Descriptor::CombinedImageSampler(
+                    view.raw(),
+                    input.layout,
+                    sampler.raw(),
+                ));
+            }
+            if let Some(ref texture) = fallback_texture {
+                descriptors.push(hal::pso::Descriptor::CombinedImageSampler(
+                    texture.view().raw(),
+                    hal::image::Layout::ShaderReadOnlyOptimal,
+                    texture.sampler().raw(),
+                ));
+            }
+            if let (Some(storage), Some(storage_buffer)) = (self.storage, storage_buffer.as_ref()) {
+                let offset = storage_frame_size * index as u64;
+                descriptors.push(hal::pso::Descriptor::Buffer(
+                    storage_buffer.raw(),
+                    Some(offset)..Some(offset + storage.size),
+                ));
+            }
+
+            let result = unsafe {
+                descriptor_pool
+                    .allocate_set(set_layout.raw())
+                    .with_context(|_| format!("Failed to allocate a {} descriptor set", name))
+                    .map_err(failure::Error::from)
+                    .and_then(|raw| {
+                        factory.write_descriptor_sets(set.writes(&raw, descriptors)?);
+                        Ok(raw)
+                    })
+            };
+            match result {
+                Ok(raw) => sets.push(raw),
+                Err(err) => {
+                    unsafe {
+                        descriptor_pool.reset();
+                        factory.destroy_descriptor_pool(descriptor_pool);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let (pipeline, pipeline_layout) =
+            match self.build_pipeline(factory, subpass, framebuffer_size, set_layout.raw()) {
+                Ok(pipeline) => pipeline,
+                Err(err) => {
+                    unsafe {
+                        descriptor_pool.reset();
+                        factory.destroy_descriptor_pool(descriptor_pool);
+                    }
+                    return Err(err);
+                }
+            };
+
+        Ok(Resources {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            buffer,
+            uniform_frame_size,
+            sets,
+            descriptor_pool,
+            samplers,
+            views,
+            fallback_texture,
+            texture_binding: 1 + self.inputs.len() as u32,
+            storage_buffer,
+            storage_frame_size,
+            vertex_buffer,
+            info: PassInfo {
+                source_size,
+                target_size: self.target_size,
+            },
+        })
+    }
+
+    // pipeline drawing the full-screen quad, shader module and pipeline creation fail instead of
+    // panicking so a driver rejecting the pass only disables it
+    fn build_pipeline<B: Backend>(
+        &self,
+        factory: &Factory<B>,
+        subpass: hal::pass::Subpass<'_, B>,
+        (framebuffer_width, framebuffer_height): (u32, u32),
+        set_layout: &B::DescriptorSetLayout,
+    ) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+        let name = &self.name;
+        let (vertex, fragment) = self.shaders()?;
+        let pipeline_layout = unsafe {
+            factory.device().create_pipeline_layout(
+                Some(set_layout),
+                None::<(ShaderStageFlags, std::ops::Range<u32>)>,
+            )
+        }
+        .with_context(|_| format!("Failed to create the {} pipeline layout", name))?;
+
+        let module = |shader: &SpirvShader, stage: &str| -> Result<B::ShaderModule, failure::Error> {
+            unsafe { shader.module(factory) }
+                .with_context(|_| format!("Failed to create the {} {} shader module", name, stage))
+                .map_err(failure::Error::from)
+        };
+        let pipelines = module(&vertex, "vertex").and_then(|vertex_module| {
+            let pipelines = module(&fragment, "fragment").and_then(|fragment_module| {
+                let pipelines = PipelinesBuilder::new()
+                    .with_pipeline(
+                        PipelineDescBuilder::new()
+                            .with_vertex_desc(&[(
+                                FullscreenVertexArgs::vertex(),
+                                hal::pso::VertexInputRate::Vertex,
+                            )])
+                            .with_input_assembler(hal::pso::InputAssemblerDesc::new(
+                                hal::Primitive::TriangleList,
+                            ))
+                            .with_shaders(util::simple_shader_set(
+                                &vertex_module,
+                                Some(&fragment_module),
+                            ))
+                            .with_layout(&pipeline_layout)
+                            .with_subpass(subpass)
+                            .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                            .with_blend_targets(vec![hal::pso::ColorBlendDesc {
+                                mask: hal::pso::ColorMask::ALL,
+                                blend: None,
+                            }]),
+                    )
+                    .build(factory, None)
+                    .with_context(|_| format!("Failed to create the {} pipeline", name))
+                    .map_err(failure::Error::from);
+                unsafe {
+                    factory.destroy_shader_module(fragment_module);
+                }
+                pipelines
+            });
+            unsafe {
+                factory.destroy_shader_module(vertex_module);
+            }
+            pipelines
+        });
+
+        match pipelines {
+            Ok(mut pipelines) => Ok((pipelines.remove(0), pipeline_layout)),
+            Err(err) => {
+                unsafe {
+                    factory.device().destroy_pipeline_layout(pipeline_layout);
+                }
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<B, U> RenderGroup<B, World> for FullscreenPass<B, U>
+where
+    B: Backend,
+    U: AsStd140 + 'static,
+    U::Std140: Copy,
+{
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+        let resources = match self.resources {
+            Some(ref mut resources) => resources,
+            None => return PrepareResult::DrawRecord,
+        };
+
+        // write to the uniform
+        let uniform = (self.uniform)(world, &resources.info).std140();
+        let upload = unsafe {
+            factory.upload_visible_buffer(
+                &mut resources.buffer,
+                resources.uniform_frame_size * index as u64,
+                &[uniform],
+            )
+        };
+        if let Err(err) = upload {
+            log::error!("Failed to upload uniforms: {}", err);
+        }
+
+        // bind the texture asset once loaded, rewriting the set of this frame picks up a reload
+        if let Some(texture) = self.texture {
+            let handle = (texture.handle)(world);
+            let storage = world.try_fetch::<AssetStorage<Texture>>();
+            let loaded = match (handle, storage.as_ref()) {
+                (Some(handle), Some(storage)) => storage.get(&handle).and_then(B::unwrap_texture),
+                _ => None,
+            };
+            if let Some(bound) = loaded.or(resources.fallback_texture.as_ref()) {
+                unsafe {
+                    factory.write_descriptor_sets(Some(hal::pso::DescriptorSetWrite {
+                        set: &resources.sets[index],
+                        binding: resources.texture_binding,
+                        array_offset: 0,
+                        descriptors: Some(hal::pso::Descriptor::CombinedImageSampler(
+                            bound.view().raw(),
+                            hal::image::Layout::ShaderReadOnlyOptimal,
+                            bound.sampler().raw(),
+                        )),
+                    }));
+                }
+            }
+        }
+
+        // the storage of this frame index was written `FRAMES` frames ago and is complete now,
+        // read it back and clear it for this frame
+        if let (Some(storage), Some(storage_buffer)) = (self.storage, resources.storage_buffer.as_mut()) {
+            let offset = resources.storage_frame_size * index as u64;
+            let range = offset..offset + storage.size;
+            let data = unsafe {
+                storage_buffer
+                    .map(factory.device(), range.clone())
+                    .and_then(|mut mapped| {
+                        mapped
+                            .read::<u8>(factory.device(), range.clone())
+                            .map(|data| data.to_vec())
+                    })
+            };
+            match data {
+                Ok(data) => (storage.read)(world, &data),
+                Err(err) => log::warn!("Failed to read back a storage buffer: {:?}", err),
+            }
+            let reset = unsafe {
+                factory.upload_visible_buffer(storage_buffer, offset, &vec![0u8; storage.size as usize])
+            };
+            if let Err(err) = reset {
+                log::warn!("Failed to clear a storage buffer: {}", err);
+            }
+        }
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        _world: &World,
+    ) {
+        let resources = match self.resources {
+            Some(ref resources) => resources,
+            None => return,
+        };
+        encoder.bind_graphics_pipeline(&resources.pipeline);
+        unsafe {
+            encoder.bind_graphics_descriptor_sets(
+                &resources.pipeline_layout,
+                0,
+                Some(&resources.sets[index]),
+                std::iter::empty(),
+            );
+            encoder.bind_vertex_buffers(0, Some((resources.vertex_buffer.raw(), 0)));
+            encoder.draw(0..6, 0..1);
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        if let Some(mut resources) = self.resources {
+            unsafe {
+                resources.descriptor_pool.reset();
+                factory.destroy_descriptor_pool(resources.descriptor_pool);
+                factory.device().destroy_graphics_pipeline(resources.pipeline);
+                factory.device().destroy_pipeline_layout(resources.pipeline_layout);
+            }
+        }
+    }
+}
@@ -1,401 +1,375 @@
 // fxaa render pipeline
-// based on tonepass pipeline from pbr-rendy: https://github.com/termhn/rendy-pbr/blob/master/src/node/pbr/tonemap.rs
+// a full-screen pass with the fxaa shader, its settings and the edge statistics it counts
 
 use amethyst::{
-    ecs::{World},
-    prelude::*,
-    window::ScreenDimensions,
-};
-use rendy::{
-    command::{QueueId, RenderPassEncoder },
-    hal::{
-        self, 
-        device::Device, pso::ShaderStageFlags, pso::DescriptorPool,
-        format::Format, image::Filter::Linear, image::WrapMode 
-    },
-    graph::{
-        render::{
-            PrepareResult,
-            SimpleGraphicsPipelineDesc,
-            SimpleGraphicsPipeline,
-            Layout, SetLayout
-        },
-        GraphContext, NodeBuffer, NodeImage, ImageAccess,
-    },
-    mesh::{
-        VertexFormat, AsVertex
-    },
-    shader::{SpirvShader},
-    memory,
-    resource::{ 
-        self,Escape,BufferInfo,Buffer,
-        Handle as RendyHandle,DescriptorSetLayout,
-        ImageViewInfo,SamplerInfo,ImageView,Sampler,
-    },
-    factory::{Factory},
+    ecs::{prelude::{Entities, Join, Read, ReadStorage, SystemData}, World, WorldExt},
+    renderer::{ActiveCamera, Camera},
 };
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, FullscreenStorage, PassInfo};
+use crate::reflect::UniformLayout;
 use glsl_layout::*;
+use serde::{Deserialize, Serialize};
 use std::mem::size_of;
 
-// load our shader pair
-lazy_static::lazy_static! {
-    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../assets/shader/fxaa.vert.spv"),
-        ShaderStageFlags::VERTEX,
-        "main",
-    ).unwrap();
+// load our fragment shader, the vertex shader is the shared full-screen one
+const FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/fxaa.frag.spv");
+// the same shader compiled with STATS, counting edge statistics into a storage buffer
+const STATS_SPIRV: &[u8] = include_bytes!("../assets/shader/fxaa_stats.frag.spv");
+
+/// FXAA pass writing an image of `target_size`, sampling the color image to anti-alias, the depth
+/// buffer it was rendered with and the exclusion mask. With `stats` the shader variant counting
+/// edge statistics is used, which needs fragment shader atomics; without it there is no storage
+/// buffer and no overhead.
+pub fn pass(target_size: (u32, u32), stats: bool) -> FullscreenPassDesc<FXAAUniformArgs> {
+    let fragment = if stats { STATS_SPIRV } else { FRAGMENT_SPIRV };
+    let desc = FullscreenPassDesc::new("FXAA", fragment, target_size, uniform_args)
+        .with_input(FullscreenInput::linear())
+        .with_input(FullscreenInput::depth())
+        .with_input(FullscreenInput::nearest())
+        .with_uniform_layout(uniform_layout());
+    if stats {
+        desc.with_storage(FullscreenStorage {
+            size: size_of::<FXAAStatsCounters>() as u64,
+            read: read_stats,
+        })
+    } else {
+        desc
+    }
+}
 
-    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
-        include_bytes!("../assets/shader/fxaa.frag.spv"),
-        ShaderStageFlags::FRAGMENT,
-        "main",
-    ).unwrap();
+pub(crate) fn uniform_layout() -> UniformLayout {
+    crate::uniform_layout!(FXAAUniformArgs {
+        source_size, target_size, enabled, luma_mode, depth_edges, depth_threshold, depth_planes,
+        alpha_mode, edge_threshold_min, edge_threshold_max, subpixel_quality, iterations, debug_view,
+    })
+}
+
+// uniforms of a frame from the fxaa settings and the camera
+fn uniform_args(world: &World, info: &PassInfo) -> FXAAUniformArgs {
+    let fxaa_settings = world.read_resource::<crate::FxaaSettings>();
+    let (source_size, target_size) = info.sizes();
+    let (near, far) = camera_planes(world);
+    let quality = fxaa_settings.quality.params();
+    FXAAUniformArgs {
+        source_size,
+        target_size,
+        enabled: fxaa_settings.enabled.into(),
+        luma_mode: fxaa_settings.luma.shader_value().into(),
+        depth_edges: fxaa_settings.depth_edges.into(),
+        depth_threshold: fxaa_settings.depth_threshold,
+        depth_planes: [near, far].into(),
+        alpha_mode: fxaa_settings.alpha_mode().shader_value().into(),
+        edge_threshold_min: quality.edge_threshold_min,
+        edge_threshold_max: quality.edge_threshold_max,
+        subpixel_quality: quality.subpixel_quality,
+        iterations: quality.iterations,
+        debug_view: fxaa_settings.debug_view.shader_value().into(),
+    }
+}
 
-    static ref SHADERS: rendy::shader::ShaderSetBuilder = rendy::shader::ShaderSetBuilder::default()
-        .with_vertex(&*VERTEX).unwrap()
-        .with_fragment(&*FRAGMENT).unwrap();
+// counters of a completed frame into the FxaaStats resource
+fn read_stats(world: &World, data: &[u8]) {
+    if data.len() < size_of::<FXAAStatsCounters>() {
+        return;
+    }
+    let counters = unsafe { std::ptr::read_unaligned(data.as_ptr() as *const FXAAStatsCounters) };
+    if let Some(mut stats) = world.try_fetch_mut::<FxaaStats>() {
+        *stats = FxaaStats::from_counters(counters);
+    }
 }
 
 // uniform arguments
 /// layout(std140, set = 0, binding = 0) uniform FXAAUniformArgs {
-///    uniform float screen_width;
-///    uniform float screen_height;
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
 ///    uniform bool enabled;
+///    uniform uint luma_mode;
+///    uniform bool depth_edges;
+///    uniform float depth_threshold;
+///    uniform vec2 depth_planes;
+///    uniform uint alpha_mode;
+///    uniform float edge_threshold_min;
+///    uniform float edge_threshold_max;
+///    uniform float subpixel_quality;
+///    uniform int iterations;
+///    uniform uint debug_view;
 /// };
 #[derive(Clone, Copy, Debug, AsStd140)]
 #[repr(C, align(4))]
 pub struct FXAAUniformArgs {
-    pub screen_width: float,
-    pub screen_height: float,
+    // size of the sampled image in texels
+    pub source_size: vec2,
+    // size of the image written to in pixels
+    pub target_size: vec2,
     pub enabled: boolean,
+    // LumaMode as integer
+    pub luma_mode: uint,
+    // also detect edges from depth discontinuities
+    pub depth_edges: boolean,
+    // relative depth difference that counts as an edge
+    pub depth_threshold: float,
+    // near and far plane of the camera, to linearize depth
+    pub depth_planes: vec2,
+    // AlphaMode as integer
+    pub alpha_mode: uint,
+    // parameters of the quality preset
+    pub edge_threshold_min: float,
+    pub edge_threshold_max: float,
+    pub subpixel_quality: float,
+    pub iterations: int,
+    // DebugView as integer
+    pub debug_view: uint,
 }
 
-/// Vertex Arguments to pass into shader.
-/// layout(location = 0) out VertexData {
-///    vec2 position;
-///    vec2 tex_coord;
-/// } vertex;
-#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, AsStd140)]
-#[repr(C, align(4))]
-pub struct FXAAVertexArgs {
-    pub position: vec2,
-    pub tex_coord: vec2,
+/// Fxaa quality presets, trading edge search length and sensitivity for speed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum FxaaQuality {
+    Low,
+    Medium,
+    High,
+    Ultra,
 }
 
-/// Required to send data into the shader.
-/// These names must match the shader.
-impl AsVertex for FXAAVertexArgs {
-    fn vertex() -> VertexFormat {
-        VertexFormat::new((
-            (Format::Rg32Sfloat, "position"),
-            (Format::Rg32Sfloat, "tex_coord"),
-        ))
+impl Default for FxaaQuality {
+    fn default() -> Self {
+        FxaaQuality::High
     }
 }
 
-
-// the pipeline itself
-#[derive(Debug, Default)]
-pub struct PipelineDesc;
-
-#[derive(Debug)]
-pub struct Pipeline<B: hal::Backend> {
-    buffer: Escape<Buffer<B>>,
-    sets: Vec<B::DescriptorSet>,
-    descriptor_pool: B::DescriptorPool,
-    image_sampler: Escape<Sampler<B>>,
-    image_view: Escape<ImageView<B>>,
-    vertex_buffer: Escape<Buffer<B>>,
-    settings: Settings,
+/// Shader parameters of a quality preset.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualityParams {
+    /// Minimum luma range that counts as an edge, stops processing dark areas.
+    pub edge_threshold_min: f32,
+    /// Luma range relative to the local maximum that counts as an edge.
+    pub edge_threshold_max: f32,
+    /// Amount of sub-pixel aliasing removal.
+    pub subpixel_quality: f32,
+    /// Maximum number of edge search steps in each direction.
+    pub iterations: i32,
 }
 
-// utility to calculte the uniform size and offset including alignment
-#[derive(Debug, PartialEq, Eq)]
-struct Settings {
-    align: u64
-}
-
-impl Settings {
-    const UNIFORM_SIZE:u64 = size_of::<FXAAUniformArgs>() as u64;
+impl FxaaQuality {
+    pub const ALL: [FxaaQuality; 4] = [
+        FxaaQuality::Low,
+        FxaaQuality::Medium,
+        FxaaQuality::High,
+        FxaaQuality::Ultra,
+    ];
+
+    pub fn params(self) -> QualityParams {
+        match self {
+            FxaaQuality::Low => QualityParams {
+                edge_threshold_min: 0.0833,
+                edge_threshold_max: 0.250,
+                subpixel_quality: 0.50,
+                iterations: 4,
+            },
+            FxaaQuality::Medium => QualityParams {
+                edge_threshold_min: 0.0625,
+                edge_threshold_max: 0.166,
+                subpixel_quality: 0.75,
+                iterations: 8,
+            },
+            FxaaQuality::High => QualityParams {
+                edge_threshold_min: 0.0312,
+                edge_threshold_max: 0.125,
+                subpixel_quality: 0.75,
+                iterations: 12,
+            },
+            FxaaQuality::Ultra => QualityParams {
+                edge_threshold_min: 0.0312,
+                edge_threshold_max: 0.063,
+                subpixel_quality: 1.0,
+                iterations: 12,
+            },
+        }
+    }
 
-    #[inline]
-    fn buffer_frame_size(&self) -> u64 {
-        ((Self::UNIFORM_SIZE - 1) / self.align + 1) * self.align
+    /// Next cheaper preset, if any.
+    pub fn lower(self) -> Option<Self> {
+        let index = Self::ALL.iter().position(|q| *q == self).unwrap();
+        index.checked_sub(1).map(|index| Self::ALL[index])
     }
 
-    #[inline]
-    fn uniform_offset(&self, index: u64) -> u64 {
-        self.buffer_frame_size() * index as u64
+    /// Next more expensive preset, if any.
+    pub fn higher(self) -> Option<Self> {
+        let index = Self::ALL.iter().position(|q| *q == self).unwrap();
+        Self::ALL.get(index + 1).cloned()
     }
 }
 
-impl<B> SimpleGraphicsPipelineDesc<B, World> for PipelineDesc
-where B: hal::Backend {
-    type Pipeline = Pipeline<B>;
+// edge statistics counted by the STATS variant of the shader
+/// layout(std430, set = 0, binding = 4) buffer FXAAStats {
+///    uint pixels;
+///    uint edge_pixels;
+///    uint search_steps;
+///    uint early_outs;
+/// };
+#[derive(Clone, Copy, Debug, Default)]
+#[repr(C)]
+struct FXAAStatsCounters {
+    pixels: u32,
+    edge_pixels: u32,
+    search_steps: u32,
+    early_outs: u32,
+}
 
-    fn images(&self) -> Vec<ImageAccess> {
-        vec![ImageAccess {
-            access: hal::image::Access::SHADER_READ,
-            usage: hal::image::Usage::SAMPLED,
-            layout: hal::image::Layout::ShaderReadOnlyOptimal,
-            stages: hal::pso::PipelineStage::FRAGMENT_SHADER,
-        }]
-    }
+/// Edge coverage of a frame, read back a few frames later when `FxaaSettings::stats` is enabled.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FxaaStats {
+    /// Pixels fxaa ran on, excluding masked pixels.
+    pub pixels: u32,
+    /// Pixels detected as being on an edge.
+    pub edge_pixels: u32,
+    /// Fraction of the processed pixels that are edges.
+    pub edge_ratio: f32,
+    /// Average number of steps the edge search took on edge pixels.
+    pub average_search_length: f32,
+    /// Fraction of the processed pixels that left early because of low contrast.
+    pub early_out_ratio: f32,
+}
 
-    fn depth_stencil(&self) -> Option<hal::pso::DepthStencilDesc> {
-        None
+impl FxaaStats {
+    fn from_counters(counters: FXAAStatsCounters) -> Self {
+        let ratio = |count: u32, total: u32| if total == 0 { 0.0 } else { count as f32 / total as f32 };
+        FxaaStats {
+            pixels: counters.pixels,
+            edge_pixels: counters.edge_pixels,
+            edge_ratio: ratio(counters.edge_pixels, counters.pixels),
+            average_search_length: ratio(counters.search_steps, counters.edge_pixels),
+            early_out_ratio: ratio(counters.early_outs, counters.pixels),
+        }
     }
+}
 
-    fn vertices(
-        &self,
-    ) -> Vec<(
-        Vec<hal::pso::Element<hal::format::Format>>,
-        hal::pso::ElemStride,
-        hal::pso::VertexInputRate,
-    )> {
-        vec![
-            FXAAVertexArgs::vertex().gfx_vertex_input_desc(hal::pso::VertexInputRate::Vertex),
-        ]
-    }
+/// How the luma used for edge detection is computed from the sampled color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LumaMode {
+    /// Square root of the Rec.601 weighted sum, approximating perceived brightness.
+    Perceptual,
+    /// Rec.709 luminance of the sampled values.
+    Linear,
+    /// Only the green channel, the cheapest estimate.
+    Green,
+    /// Luma precomputed into the alpha channel by the main pass. `DrawShadedDesc` writes the
+    /// material alpha there instead, so this needs a custom main pass writing luma.
+    Alpha,
+}
 
-    fn load_shader_set(
-        &self,
-        factory: &mut Factory<B>,
-        _world: &World,
-    ) -> rendy::shader::ShaderSet<B> {
-        SHADERS.build(factory, Default::default()).unwrap()
+impl Default for LumaMode {
+    fn default() -> Self {
+        LumaMode::Perceptual
     }
+}
 
-    fn layout(&self) -> Layout {
-        Layout {
-            sets: vec![SetLayout {
-                bindings: vec![
-                    hal::pso::DescriptorSetLayoutBinding {
-                        binding: 0,
-                        ty: hal::pso::DescriptorType::UniformBuffer,
-                        count: 1,
-                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
-                        immutable_samplers: false,
-                    },
-                    hal::pso::DescriptorSetLayoutBinding {
-                        binding: 1,
-                        ty: hal::pso::DescriptorType::CombinedImageSampler,
-                        count: 1,
-                        stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
-                        immutable_samplers: false,
-                    },
-                ],
-            }],
-            push_constants: Vec::new(),
+impl LumaMode {
+    // value of the matching LUMA_* define in fxaa.frag
+    fn shader_value(self) -> u32 {
+        match self {
+            LumaMode::Perceptual => 0,
+            LumaMode::Linear => 1,
+            LumaMode::Green => 2,
+            LumaMode::Alpha => 3,
         }
     }
+}
 
-    fn build<'a>(
-        self,
-        ctx: &GraphContext<B>,
-        factory: &mut Factory<B>,
-        _queue: QueueId,
-        _world: &World,
-        buffers: Vec<NodeBuffer>,
-        images: Vec<NodeImage>,
-        set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
-    ) -> Result<Pipeline<B>, failure::Error> {
-        assert!(buffers.is_empty());
-        assert!(images.len() == 1);
-        assert!(set_layouts.len() == 1);
-
-        let align_limit = hal::adapter::PhysicalDevice::limits(factory.physical()).min_uniform_buffer_offset_alignment;
-        let settings = Settings { align:align_limit };
-        let frames = 3;
-
-        let mut descriptor_pool = unsafe {
-            factory.create_descriptor_pool(
-                frames,
-                vec![
-                    hal::pso::DescriptorRangeDesc {
-                        ty: hal::pso::DescriptorType::UniformBuffer,
-                        count: frames,
-                    },
-                    hal::pso::DescriptorRangeDesc {
-                        ty: hal::pso::DescriptorType::CombinedImageSampler,
-                        count: frames,
-                    },
-                ],
-                hal::pso::DescriptorPoolCreateFlags::empty(),
-            )?
-        };
-
-        let image_sampler = factory
-            .create_sampler(SamplerInfo {
-                min_filter:Linear,
-                mag_filter:Linear,
-                mip_filter:Linear,
-                wrap_mode:(WrapMode::Clamp,WrapMode::Clamp,WrapMode::Clamp),
-                lod_bias:hal::image::Lod::ZERO,
-                lod_range:hal::image::Lod::ZERO .. hal::image::Lod::MAX,
-                comparison:None,
-                border:[0.0,0.0,0.0,0.0].into(),
-                normalized:true,
-                anisotropic:hal::image::Anisotropic::Off
-            })
-            .unwrap();
-
-        let image_handle = ctx
-            .get_image(images[0].id)
-            .expect("Input image missing");
-
-        let image_view = factory
-            .create_image_view(
-                image_handle.clone(),
-                ImageViewInfo {
-                    view_kind: resource::ViewKind::D2,
-                    format: hal::format::Format::Rgba8Unorm, //Rgba32Sfloat,
-                    swizzle: hal::format::Swizzle::NO,
-                    range: images[0].range.clone(),
-                },
-            )
-            .expect("Could not create input image view");
+/// What the fxaa pass writes to the alpha channel of its output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AlphaMode {
+    /// Always 1.0, the output is opaque.
+    Opaque,
+    /// The alpha of the source pixel, unaffected by anti-aliasing.
+    Preserve,
+    /// Alpha sampled at the anti-aliased position along with the color.
+    AntiAlias,
+}
 
-        let buffer = factory
-            .create_buffer(
-                BufferInfo {
-                    size: settings.buffer_frame_size() * frames as u64,
-                    usage: hal::buffer::Usage::UNIFORM,
-                },
-                rendy::memory::MemoryUsageValue::Dynamic,
-            )
-            .unwrap();
+impl Default for AlphaMode {
+    fn default() -> Self {
+        AlphaMode::Opaque
+    }
+}
 
-        let mut sets = Vec::with_capacity(frames);
-        for index in 0..frames {
-            unsafe {
-                let set = descriptor_pool.allocate_set(&set_layouts[0].raw()).unwrap();
-                factory.write_descriptor_sets(vec![
-                    hal::pso::DescriptorSetWrite {
-                        set: &set,
-                        binding: 0,
-                        array_offset: 0,
-                        descriptors: Some(hal::pso::Descriptor::Buffer(
-                            buffer.raw(),
-                            Some(settings.uniform_offset(index as u64))
-                            ..Some(
-                                settings.uniform_offset(index as u64) + Settings::UNIFORM_SIZE,
-                            ),
-                        )),
-                    },
-                    hal::pso::DescriptorSetWrite {
-                        set: &set,
-                        binding: 1,
-                        array_offset: 0,
-                        descriptors: Some(hal::pso::Descriptor::CombinedImageSampler(
-                            image_view.raw(),
-                            hal::image::Layout::ShaderReadOnlyOptimal,
-                            image_sampler.raw()
-                        )),
-                    }
-                ]);
-                sets.push(set);
-            }
+impl AlphaMode {
+    // value of the matching ALPHA_* define in fxaa.frag
+    fn shader_value(self) -> u32 {
+        match self {
+            AlphaMode::Opaque => 0,
+            AlphaMode::Preserve => 1,
+            AlphaMode::AntiAlias => 2,
         }
-
-        // create a static vertex buffer
-        let vbuf_size = FXAAVertexArgs::vertex().stride as u64 * 6;
-        let mut vertex_buffer = factory.create_buffer(
-            BufferInfo {
-                size: vbuf_size,
-                usage: hal::buffer::Usage::VERTEX
-            },
-            memory::Dynamic,
-        ).unwrap();
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut vertex_buffer,
-                    0,
-                    &[
-                        FXAAVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
-                        FXAAVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
-                        FXAAVertexArgs { position:[-1f32,-1f32].into(), tex_coord:[0f32,0f32].into() },
-                        FXAAVertexArgs { position:[1f32,-1f32].into(), tex_coord:[1f32,0f32].into() },
-                        FXAAVertexArgs { position:[-1f32,1f32].into(), tex_coord:[0f32,1f32].into() },
-                        FXAAVertexArgs { position:[1f32,1f32].into(), tex_coord:[1f32,1f32].into() },
-                    ],
-                )
-                .unwrap();
-        }
-
-        Ok(Pipeline {
-            buffer,
-            sets,
-            image_view,
-            image_sampler,
-            descriptor_pool,
-            settings,
-            vertex_buffer,
-        })
     }
 }
 
-impl<B> SimpleGraphicsPipeline<B, World> for Pipeline<B>
-where
-    B: hal::Backend,
-{
-    type Desc = PipelineDesc;
-
-    fn prepare(
-        &mut self,
-        factory: &Factory<B>,
-        _queue: QueueId,
-        _set_layouts: &[RendyHandle<DescriptorSetLayout<B>>],
-        index: usize,
-        world: &World,
-    ) -> PrepareResult {
-        let dimensions = world.read_resource::<ScreenDimensions>();
-        let fxaa_settings = world.read_resource::<crate::FxaaSettings>();
+/// Diagnostic output of the fxaa pass, replacing the anti-aliased image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DebugView {
+    Off,
+    /// Luma as used for edge detection.
+    Luma,
+    /// Processed pixels, red on horizontal and blue on vertical edges.
+    Edges,
+    /// Excluded pixels in red and depth edges in green.
+    Mask,
+}
 
-        // write to the uniform
-        unsafe {
-            factory
-                .upload_visible_buffer(
-                    &mut self.buffer,
-                    self.settings.uniform_offset(index as u64),
-                    &[FXAAUniformArgs {
-                        screen_width: dimensions.width(),
-                        screen_height: dimensions.height(),
-                        enabled: fxaa_settings.enabled.into(),
-                    }.std140()],
-                )
-                .unwrap()
-        };
-        
-        PrepareResult::DrawRecord
+impl Default for DebugView {
+    fn default() -> Self {
+        DebugView::Off
     }
+}
 
-    fn draw(
-        &mut self,
-        layout: &B::PipelineLayout,
-        mut encoder: RenderPassEncoder<'_, B>,
-        index: usize,
-        _world: &World,
-    ) {
-        unsafe {
-            encoder.bind_graphics_descriptor_sets(
-                layout,
-                0,
-                Some(&self.sets[index]),
-                std::iter::empty(),
-            );
+impl DebugView {
+    // value of the matching DEBUG_* define in fxaa.frag
+    fn shader_value(self) -> u32 {
+        match self {
+            DebugView::Off => 0,
+            DebugView::Luma => 1,
+            DebugView::Edges => 2,
+            DebugView::Mask => 3,
+        }
+    }
+}
 
-            encoder.bind_vertex_buffers(0, Some((self.vertex_buffer.raw(), 0)));
+// near and far plane of the active camera, or the first camera if none is active
+fn camera_planes(world: &World) -> (f32, f32) {
+    let (active, entities, cameras) = <(
+        Option<Read<'_, ActiveCamera>>,
+        Entities<'_>,
+        ReadStorage<'_, Camera>,
+    )>::fetch(world);
+    let camera = active
+        .and_then(|active| active.entity)
+        .and_then(|entity| cameras.get(entity))
+        .or_else(|| (&entities, &cameras).join().map(|(_, camera)| camera).next());
+    camera
+        .and_then(|camera| camera.projection().as_perspective())
+        .map_or((0.1, 2000.0), |p| (p.near(), p.far()))
+}
 
-            encoder.draw(0..6, 0..1);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use spirv_reflect::{types::ReflectDescriptorType, ShaderModule};
+
+    // storage buffer bindings of a shader
+    fn storage_bindings(spirv: &[u8]) -> Vec<u32> {
+        let module = ShaderModule::load_u8_data(spirv).unwrap();
+        let bindings = module.enumerate_descriptor_bindings(None).unwrap();
+        bindings
+            .iter()
+            .filter(|b| b.descriptor_type == ReflectDescriptorType::StorageBuffer)
+            .map(|b| b.binding)
+            .collect()
     }
 
-    fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
-        unsafe {
-            self.descriptor_pool.reset();
-            factory.destroy_descriptor_pool(self.descriptor_pool);
-        }
+    #[test]
+    fn only_the_stats_variant_has_the_storage_buffer() {
+        assert!(storage_bindings(FRAGMENT_SPIRV).is_empty());
+        assert_eq!(storage_bindings(STATS_SPIRV), vec![4]);
+        pass((64, 64), false).validate().unwrap();
+        pass((64, 64), true).validate().unwrap();
     }
-}
\ No newline at end of file
+}
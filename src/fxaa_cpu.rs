@@ -0,0 +1,178 @@
+// cpu fxaa
+// a straight port of the luma based part of fxaa.frag, so image files can be anti-aliased without a
+// gpu and the result compared against captured frames, depth edges and the mask aren't supported
+
+use crate::fxaa::{LumaMode, QualityParams};
+use crate::quality::Image;
+
+// search step multipliers, the QUALITY(q) macro of fxaa.frag
+fn quality_step(q: i32) -> f32 {
+    match q {
+        0..=4 => 1.0,
+        5 => 1.5,
+        6..=9 => 2.0,
+        10 => 4.0,
+        _ => 8.0,
+    }
+}
+
+/// Luma according to the mode, like rgb2luma in fxaa.frag.
+pub fn luma(mode: LumaMode, rgba: [f32; 4]) -> f32 {
+    match mode {
+        LumaMode::Perceptual => (rgba[0] * 0.299 + rgba[1] * 0.587 + rgba[2] * 0.114).sqrt(),
+        LumaMode::Linear => rgba[0] * 0.2126 + rgba[1] * 0.7152 + rgba[2] * 0.0722,
+        LumaMode::Green => rgba[1],
+        LumaMode::Alpha => rgba[3],
+    }
+}
+
+/// Anti-alias an image with the given preset parameters.
+pub fn fxaa(image: &Image, params: QualityParams, mode: LumaMode) -> Image {
+    let mut output = Image::new(image.width, image.height);
+    for y in 0..image.height {
+        for x in 0..image.width {
+            let color = fxaa_pixel(image, params, mode, x as i64, y as i64);
+            output.set(x, y, color);
+        }
+    }
+    output
+}
+
+fn fxaa_pixel(image: &Image, params: QualityParams, mode: LumaMode, x: i64, y: i64) -> [f32; 4] {
+    let luma_at = |dx: i64, dy: i64| luma(mode, image.get(x + dx, y + dy));
+    // sample positions are in pixels, with pixel centers at .5
+    let (center_x, center_y) = (x as f32 + 0.5, y as f32 + 0.5);
+
+    let color_center = image.get(x, y);
+    let luma_center = luma(mode, color_center);
+    let luma_down = luma_at(0, -1);
+    let luma_up = luma_at(0, 1);
+    let luma_left = luma_at(-1, 0);
+    let luma_right = luma_at(1, 0);
+
+    let luma_min = luma_center.min(luma_down.min(luma_up).min(luma_left.min(luma_right)));
+    let luma_max = luma_center.max(luma_down.max(luma_up).max(luma_left.max(luma_right)));
+    let luma_range = luma_max - luma_min;
+
+    if luma_range < params.edge_threshold_min.max(luma_max * params.edge_threshold_max) {
+        return color_center;
+    }
+
+    let luma_down_left = luma_at(-1, -1);
+    let luma_up_right = luma_at(1, 1);
+    let luma_up_left = luma_at(-1, 1);
+    let luma_down_right = luma_at(1, -1);
+
+    let luma_down_up = luma_down + luma_up;
+    let luma_left_right = luma_left + luma_right;
+    let luma_left_corners = luma_down_left + luma_up_left;
+    let luma_down_corners = luma_down_left + luma_down_right;
+    let luma_right_corners = luma_down_right + luma_up_right;
+    let luma_up_corners = luma_up_right + luma_up_left;
+
+    let edge_horizontal = (-2.0 * luma_left + luma_left_corners).abs()
+        + (-2.0 * luma_center + luma_down_up).abs() * 2.0
+        + (-2.0 * luma_right + luma_right_corners).abs();
+    let edge_vertical = (-2.0 * luma_up + luma_up_corners).abs()
+        + (-2.0 * luma_center + luma_left_right).abs() * 2.0
+        + (-2.0 * luma_down + luma_down_corners).abs();
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    let luma1 = if is_horizontal { luma_down } else { luma_left };
+    let luma2 = if is_horizontal { luma_up } else { luma_right };
+    let gradient1 = luma1 - luma_center;
+    let gradient2 = luma2 - luma_center;
+    let is1_steepest = gradient1.abs() >= gradient2.abs();
+    let gradient_scaled = 0.25 * gradient1.abs().max(gradient2.abs());
+
+    let mut step_length = 1.0;
+    let luma_local_average;
+    if is1_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma1 + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma2 + luma_center);
+    }
+
+    // positions along the edge, shifted half a pixel towards the steepest side
+    let (mut current_x, mut current_y) = (center_x, center_y);
+    if is_horizontal {
+        current_y += step_length * 0.5;
+    } else {
+        current_x += step_length * 0.5;
+    }
+    let (offset_x, offset_y) = if is_horizontal { (1.0, 0.0) } else { (0.0, 1.0) };
+    let (mut uv1_x, mut uv1_y) = (current_x - offset_x * quality_step(0), current_y - offset_y * quality_step(0));
+    let (mut uv2_x, mut uv2_y) = (current_x + offset_x * quality_step(0), current_y + offset_y * quality_step(0));
+
+    let sample_luma = |px: f32, py: f32| luma(mode, image.sample(px, py));
+    let mut luma_end1 = sample_luma(uv1_x, uv1_y) - luma_local_average;
+    let mut luma_end2 = sample_luma(uv2_x, uv2_y) - luma_local_average;
+    let mut reached1 = luma_end1.abs() >= gradient_scaled;
+    let mut reached2 = luma_end2.abs() >= gradient_scaled;
+    let mut reached_both = reached1 && reached2;
+
+    if !reached1 {
+        uv1_x -= offset_x * quality_step(1);
+        uv1_y -= offset_y * quality_step(1);
+    }
+    if !reached2 {
+        uv2_x += offset_x * quality_step(1);
+        uv2_y += offset_y * quality_step(1);
+    }
+
+    if !reached_both {
+        for i in 2..params.iterations {
+            if !reached1 {
+                luma_end1 = sample_luma(uv1_x, uv1_y) - luma_local_average;
+            }
+            if !reached2 {
+                luma_end2 = sample_luma(uv2_x, uv2_y) - luma_local_average;
+            }
+            reached1 = luma_end1.abs() >= gradient_scaled;
+            reached2 = luma_end2.abs() >= gradient_scaled;
+            reached_both = reached1 && reached2;
+
+            if !reached1 {
+                uv1_x -= offset_x * quality_step(i);
+                uv1_y -= offset_y * quality_step(i);
+            }
+            if !reached2 {
+                uv2_x += offset_x * quality_step(i);
+                uv2_y += offset_y * quality_step(i);
+            }
+            if reached_both {
+                break;
+            }
+        }
+    }
+
+    let distance1 = if is_horizontal { center_x - uv1_x } else { center_y - uv1_y };
+    let distance2 = if is_horizontal { uv2_x - center_x } else { uv2_y - center_y };
+    let is_direction1 = distance1 < distance2;
+    let distance_final = distance1.min(distance2);
+    let edge_thickness = distance1 + distance2;
+
+    let is_luma_center_smaller = luma_center < luma_local_average;
+    let correct_variation1 = (luma_end1 < 0.0) != is_luma_center_smaller;
+    let correct_variation2 = (luma_end2 < 0.0) != is_luma_center_smaller;
+    let correct_variation = if is_direction1 { correct_variation1 } else { correct_variation2 };
+
+    let pixel_offset = -distance_final / edge_thickness + 0.5;
+    let mut final_offset = if correct_variation { pixel_offset } else { 0.0 };
+
+    let luma_average = (1.0 / 12.0)
+        * (2.0 * (luma_down_up + luma_left_right) + luma_left_corners + luma_right_corners);
+    let sub_pixel_offset1 = ((luma_average - luma_center).abs() / luma_range.max(0.0001)).max(0.0).min(1.0);
+    let sub_pixel_offset2 = (-2.0 * sub_pixel_offset1 + 3.0) * sub_pixel_offset1 * sub_pixel_offset1;
+    let sub_pixel_offset_final = sub_pixel_offset2 * sub_pixel_offset2 * params.subpixel_quality;
+    final_offset = final_offset.max(sub_pixel_offset_final);
+
+    let (mut final_x, mut final_y) = (center_x, center_y);
+    if is_horizontal {
+        final_y += final_offset * step_length;
+    } else {
+        final_x += final_offset * step_length;
+    }
+    image.sample(final_x, final_y)
+}
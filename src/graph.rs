@@ -7,16 +7,111 @@ use amethyst::{
         types::DefaultBackend,
         Factory, Format, GraphBuilder, GraphCreator, Kind,
         RenderGroupDesc, SubpassBuilder,
-        rendy::graph::render::{SimpleGraphicsPipeline,RenderGroupBuilder},
+        rendy::graph::{
+            render::RenderGroupBuilder,
+            ImageId, NodeDesc, NodeId,
+        },
+        rendy::{hal, wsi::Surface},
     },
     window::{ScreenDimensions, Window },
+    log,
 };
+use crate::mask::DrawMaskDesc;
+use crate::postprocess::{ChainPass, PostEffect, PostProcessChain};
+use crate::profiling::{Marker, ProfilingConfig, TimestampDesc};
+use crate::scale::{RenderScale, RenderScaleConfig};
+use crate::ssaa::SsaaSettings;
+use crate::upscale::Upscaler;
+use crate::fxaa::AlphaMode;
+use crate::FxaaSettings;
+use std::sync::Mutex;
 //use crate::fxaa::DrawFXAADesc;
 
+/// Resource recording the post-processing passes whose pipeline couldn't be built. The graph is
+/// then rebuilt without them instead of aborting, presenting the image of the main pass unprocessed
+/// once no pass is left. They are tried again when the graph settings change.
+#[derive(Debug, Default)]
+pub struct PostProcessFallback {
+    failed: Mutex<Vec<String>>,
+}
+
+impl PostProcessFallback {
+    /// Log the error and leave the pass out of the graph, from a pipeline build holding only a
+    /// shared reference to the world.
+    pub fn report(world: &World, pass: &str, err: &failure::Error) {
+        let causes: Vec<String> = err.iter_chain().map(|cause| cause.to_string()).collect();
+        log::warn!("{} pass disabled, continuing without it: {}", pass, causes.join(": "));
+        if let Some(fallback) = world.try_fetch::<PostProcessFallback>() {
+            let mut failed = fallback.failed.lock().unwrap();
+            if !failed.iter().any(|name| name == pass) {
+                failed.push(pass.to_string());
+            }
+        }
+    }
+
+    /// Names of the passes that failed, in the order they were reported.
+    pub fn failed(&self) -> Vec<String> {
+        self.failed.lock().unwrap().clone()
+    }
+
+    pub fn clear(&self) {
+        self.failed.lock().unwrap().clear();
+    }
+}
+
 #[derive(Default)]
 pub struct RenderGraph {
     dimensions: Option<ScreenDimensions>,
+    settings: GraphSettings,
+    // post-processing passes left out of the graph after failing
+    failed: Vec<String>,
     dirty: bool,
+    headless: bool,
+}
+
+// settings the graph is built with, a change requires a rebuild
+#[derive(Clone, Debug, Default, PartialEq)]
+struct GraphSettings {
+    scale: RenderScale,
+    // the fxaa input is stored as sRGB
+    srgb_input: bool,
+    // the images are cleared to transparent so alpha survives to the output
+    transparent: bool,
+    // supersampling factor, replacing fxaa with a downsample pass
+    ssaa: Option<u32>,
+    // upscale the scaled image after fxaa instead of sampling it bilinearly
+    upscaler: Upscaler,
+    // fxaa counts edge statistics, with the shader variant writing the stats buffer
+    fxaa_stats: bool,
+    // enabled post-processing passes in order
+    chain: Vec<PostEffect>,
+}
+
+impl GraphSettings {
+    fn fetch(world: &World) -> Self {
+        let fxaa_settings = world.try_fetch::<FxaaSettings>();
+        GraphSettings {
+            scale: world.try_fetch::<RenderScale>().map_or(RenderScale::default(), |s| *s),
+            srgb_input: fxaa_settings.as_ref().map_or(false, |s| s.srgb_input),
+            transparent: fxaa_settings.as_ref().map_or(false, |s| s.alpha_mode() != AlphaMode::Opaque),
+            ssaa: world.try_fetch::<SsaaSettings>().and_then(|s| s.active_factor()),
+            upscaler: world.try_fetch::<RenderScaleConfig>().map_or(Upscaler::default(), |c| c.upscaler),
+            fxaa_stats: fxaa_settings.as_ref().map_or(false, |s| s.stats),
+            chain: world
+                .try_fetch::<PostProcessChain>()
+                .map_or_else(|| PostProcessChain::default().effects(), |c| c.effects()),
+        }
+    }
+}
+
+impl RenderGraph {
+    // graph rendering into an offscreen target instead of the window surface
+    pub fn headless() -> Self {
+        RenderGraph {
+            headless: true,
+            ..Default::default()
+        }
+    }
 }
 
 impl GraphCreator<DefaultBackend> for RenderGraph {
@@ -30,6 +125,26 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
             self.dimensions = new_dimensions.map(|d| d.deref().clone());
             return false;
         }
+
+        // Rebuild when the settings the graph was built with change
+        let new_settings = GraphSettings::fetch(world);
+        let fallback = world.try_fetch::<PostProcessFallback>();
+        if self.settings != new_settings {
+            self.settings = new_settings;
+            self.dirty = true;
+            // passes that failed get another chance with the new settings
+            if let Some(ref fallback) = fallback {
+                fallback.clear();
+            }
+        }
+
+        // Rebuild without the passes whose pipeline failed since
+        if let Some(failed) = fallback.map(|f| f.failed()) {
+            if self.failed != failed {
+                self.failed = failed;
+                self.dirty = true;
+            }
+        }
         self.dirty
     }
 
@@ -48,24 +163,107 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
 
         self.dirty = false;
 
-        // Retrieve a reference to the target window, which is created by the WindowBundle
-        let window = <ReadExpect<'_, Window>>::fetch(world);
         let dimensions = self.dimensions.as_ref().unwrap();
-        let window_kind = Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1);
 
-        // Create a new drawing surface in our window
-        let surface = factory.create_surface(&window);
-        let surface_format = factory.get_surface_format(&surface);
+        // Create a new drawing surface in the target window, which is created by the WindowBundle.
+        // Without a window the final image is only read back.
+        let (surface, window_size) = if self.headless {
+            (None, (dimensions.width() as u32, dimensions.height() as u32))
+        } else {
+            let window = <ReadExpect<'_, Window>>::fetch(world);
+            // the surface matches the physical size of the window, which differs from the logical
+            // size on HiDPI displays
+            let hidpi = window.get_hidpi_factor();
+            let window_size = match window.get_inner_size() {
+                Some(size) => physical_size(size.into(), hidpi),
+                None => (dimensions.width() as u32, dimensions.height() as u32),
+            };
+            (Some(factory.create_surface(&window)), window_size)
+        };
+        let window_kind = Kind::D2(window_size.0, window_size.1, 1, 1);
+        let surface_format = match surface {
+            Some(ref surface) => factory.get_surface_format(surface),
+            None => Format::Rgba8Srgb,
+        };
 
         // Begin building our RenderGraph
         let mut graph_builder = GraphBuilder::new();
 
-        // HDR color output
+        // Supersampling is limited to the factors whose image the device can create
+        let ssaa = self.settings.ssaa.and_then(|factor| {
+            let max_size = hal::adapter::PhysicalDevice::limits(factory.physical()).max_image_2d_size;
+            let fitting = crate::ssaa::fitting_factor(factor, window_size, max_size);
+            match fitting {
+                Some(fitting) if fitting < factor => log::warn!(
+                    "Supersampling at {}x instead of {}x, images are limited to {} pixels",
+                    fitting,
+                    factor,
+                    max_size
+                ),
+                Some(_) => (),
+                None => log::warn!(
+                    "Supersampling disabled, a {}x{} window exceeds the {} pixel image limit",
+                    window_size.0,
+                    window_size.1,
+                    max_size
+                ),
+            }
+            fitting
+        });
+
+        // The main pass renders at the render scale, post-processing writes the window sized image.
+        // Supersampling renders at a multiple of the window size instead and filters it down.
+        let (scaled_width, scaled_height) = match ssaa {
+            Some(factor) => (window_size.0 * factor, window_size.1 * factor),
+            None => self.settings.scale.apply(window_size.0, window_size.1),
+        };
+        // A scaled down image is upscaled after fxaa by the upscaler, if it isn't bilinear
+        let upscale_from = match self.settings.upscaler {
+            Upscaler::Fsr if ssaa.is_none() && scaled_width < window_size.0 => {
+                Some((scaled_width, scaled_height))
+            }
+            _ => None,
+        };
+
+        // Edge statistics are written with fragment shader atomics, without them fxaa runs the
+        // variant that doesn't count
+        let fxaa_stats = self.settings.fxaa_stats && {
+            let features = hal::adapter::PhysicalDevice::features(factory.physical());
+            let supported = features.contains(hal::Features::FRAGMENT_STORES_AND_ATOMICS);
+            if !supported {
+                log::warn!("FXAA statistics need fragment shader atomics, which this device lacks");
+            }
+            supported
+        };
+
+        // Check the post-processing shaders before adding their passes. Broken passes and the ones
+        // whose pipeline failed are left out of the chain, without any pass the main pass is
+        // presented directly.
+        let passes = crate::postprocess::chain_passes(
+            &self.settings.chain,
+            ssaa,
+            upscale_from,
+            fxaa_stats,
+            window_size,
+            &self.failed,
+        );
+        let profiling = world.try_fetch::<ProfilingConfig>().map_or(false, |c| c.enabled);
+        if passes.is_empty() {
+            return fallback_graph(graph_builder, factory, surface, window_kind, surface_format, profiling);
+        }
+
+        let scaled_kind = Kind::D2(scaled_width, scaled_height, 1, 1);
+
+        // Background alpha, transparent when fxaa passes alpha through
+        let clear_alpha = if self.settings.transparent { 0.0 } else { 1.0 };
+
+        // HDR color output, stored as sRGB if fxaa should filter decoded values
+        let hdr_format = if self.settings.srgb_input { Format::Rgba8Srgb } else { Format::Rgba8Unorm };
         let hdr = graph_builder.create_image(
-            Kind::D2(dimensions.width() as u32, dimensions.height() as u32, 1, 1),
+            scaled_kind,
             1,
-            Format::Rgba8Unorm,
-            Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
+            hdr_format,
+            Some(ClearValue::Color([0.0, 0.0, 0.0, clear_alpha].into())),
         );
 
         // Color and depth outputs
@@ -73,38 +271,284 @@ impl GraphCreator<DefaultBackend> for RenderGraph {
             window_kind,
             1,
             surface_format,
-            Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
+            Some(ClearValue::Color([0.0, 0.0, 0.0, clear_alpha].into())),
         );
         let depth = graph_builder.create_image(
-            window_kind,
+            scaled_kind,
             1,
             Format::D32Sfloat,
             Some(ClearValue::DepthStencil(ClearDepthStencil(1.0, 0))),
         );
 
+        // GPU timestamps around the passes when profiling is enabled
+        let main_begin = add_timestamp(&mut graph_builder, profiling, Marker::MainBegin, &[]);
+
         // Main render pass
-        let main_pass = graph_builder.add_node(
-            SubpassBuilder::new()
-                .with_group(DrawShadedDesc::default().builder())
-                .with_color(hdr)
-                .with_depth_stencil(depth)
-                .into_pass(),
-        );
+        let mut main_subpass = SubpassBuilder::new()
+            .with_group(DrawShadedDesc::default().builder())
+            .with_color(hdr)
+            .with_depth_stencil(depth);
+        if let Some(node) = main_begin {
+            main_subpass.add_dependency(node);
+        }
+        let main_pass = graph_builder.add_node(main_subpass.into_pass());
+        let main_end = add_timestamp(&mut graph_builder, profiling, Marker::MainEnd, &[main_pass]);
 
-        // FXAA pass
-        let fxaa_pass = graph_builder.add_node(
-            crate::fxaa::Pipeline::builder()
-                .with_image(hdr)
-                .into_subpass()
-                .with_dependency(main_pass)
-                .with_color(color)
-                .into_pass()
-        );
+        // Exclusion mask flagging pixels fxaa should leave alone, drawn from the excluded entities
+        // depth tested against the main pass
+        let mask = if passes.iter().any(|pass| match pass { ChainPass::Fxaa(_) => true, _ => false }) {
+            let mask = graph_builder.create_image(
+                scaled_kind,
+                1,
+                Format::R8Unorm,
+                Some(ClearValue::Color([0.0, 0.0, 0.0, 0.0].into())),
+            );
+            let mask_pass = graph_builder.add_node(
+                SubpassBuilder::new()
+                    .with_group(DrawMaskDesc::default().builder())
+                    .with_dependency(main_pass)
+                    .with_color(mask)
+                    .with_depth_stencil(depth)
+                    .into_pass(),
+            );
+            Some((mask, mask_pass))
+        } else {
+            None
+        };
+
+        // Post-processing passes ping-ponging between two intermediate images of each size they
+        // write, the window size or the render size before upscaling. The last one writes the
+        // output, the first reads the main pass, which may be scaled.
+        let mut intermediates: Vec<((u32, u32), [ImageId; 2], usize)> = Vec::new();
+        let last = passes.len() - 1;
+        let mut input = hdr;
+        let mut dependencies: Vec<NodeId> = Some(main_pass).into_iter().chain(main_end).collect();
+        let mut timed = false;
+        for (index, pass) in passes.into_iter().enumerate() {
+            let output = if index == last {
+                color
+            } else {
+                let size = pass.target_size();
+                let slot = match intermediates.iter().position(|(s, _, _)| *s == size) {
+                    Some(slot) => slot,
+                    None => {
+                        let kind = Kind::D2(size.0, size.1, 1, 1);
+                        let images = [
+                            graph_builder.create_image(kind, 1, hdr_format, None),
+                            graph_builder.create_image(kind, 1, hdr_format, None),
+                        ];
+                        intermediates.push((size, images, 0));
+                        intermediates.len() - 1
+                    }
+                };
+                let (_, images, next) = &mut intermediates[slot];
+                let image = images[*next % 2];
+                *next += 1;
+                image
+            };
+            // the fxaa timings cover the anti-aliasing pass alone, bracketed by its own timestamps
+            let anti_aliasing = match pass {
+                ChainPass::Downsample(_) | ChainPass::Fxaa(_) => true,
+                _ => false,
+            };
+            if anti_aliasing {
+                let mut after = dependencies.clone();
+                after.extend(mask.map(|(_, mask_pass)| mask_pass));
+                let fxaa_begin =
+                    add_timestamp(&mut graph_builder, profiling, Marker::FxaaBegin, &after);
+                dependencies.extend(fxaa_begin);
+            }
+            let mut subpass = match pass {
+                ChainPass::Downsample(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::Fxaa(desc) => {
+                    let (mask, mask_pass) = mask.expect("the mask is drawn for fxaa");
+                    desc.builder()
+                        .with_image(input)
+                        .with_image(depth)
+                        .with_image(mask)
+                        .into_subpass()
+                        .with_dependency(mask_pass)
+                }
+                ChainPass::Sharpen(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::ColorGrade(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::Dither(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::Easu(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::Rcas(desc) => desc.builder().with_image(input).into_subpass(),
+                ChainPass::Color(desc) => desc.builder().with_image(input).into_subpass(),
+            }
+            .with_color(output);
+            for node in dependencies.drain(..) {
+                subpass.add_dependency(node);
+            }
+            let node = graph_builder.add_node(subpass.into_pass());
+            dependencies.push(node);
+            if anti_aliasing {
+                timed = true;
+                let fxaa_end =
+                    add_timestamp(&mut graph_builder, profiling, Marker::FxaaEnd, &[node]);
+                dependencies.extend(fxaa_end);
+            }
+            input = output;
+        }
+        // without an anti-aliasing pass its timestamps follow the chain, keeping timings complete
+        if !timed {
+            let fxaa_begin =
+                add_timestamp(&mut graph_builder, profiling, Marker::FxaaBegin, &dependencies);
+            dependencies.extend(fxaa_begin);
+            let fxaa_end =
+                add_timestamp(&mut graph_builder, profiling, Marker::FxaaEnd, &dependencies);
+            dependencies.extend(fxaa_end);
+        }
+
+        // Screenshot readback of the final and the pre-fxaa image, with supersampling the latter is
+        // the full resolution frame
+        let capture_desc = if ssaa.is_some() {
+            crate::screenshot::CaptureDesc::supersampled()
+        } else {
+            crate::screenshot::CaptureDesc::default()
+        };
+        let mut capture = capture_desc.builder().with_image(color).with_image(hdr);
+        for node in dependencies {
+            capture.add_dependency(node);
+        }
+        let capture = graph_builder.add_node(capture);
 
         // Finally, add the pass to the graph
-        let _present = graph_builder
-            .add_node(PresentNode::builder(factory, surface, color).with_dependency(fxaa_pass));
+        if let Some(surface) = surface {
+            let _present = graph_builder
+                .add_node(PresentNode::builder(factory, surface, color).with_dependency(capture));
+        }
 
         graph_builder
     }
-}
\ No newline at end of file
+}
+
+// graph without post-processing, the main pass renders at the window size straight into the
+// presented image
+fn fallback_graph(
+    mut graph_builder: GraphBuilder<DefaultBackend, World>,
+    factory: &mut Factory<DefaultBackend>,
+    surface: Option<Surface<DefaultBackend>>,
+    window_kind: Kind,
+    surface_format: Format,
+    profiling: bool,
+) -> GraphBuilder<DefaultBackend, World> {
+    use amethyst::renderer::rendy::{
+        graph::present::PresentNode,
+        hal::command::{ClearDepthStencil, ClearValue},
+    };
+
+    let color = graph_builder.create_image(
+        window_kind,
+        1,
+        surface_format,
+        Some(ClearValue::Color([0.0, 0.0, 0.0, 1.0].into())),
+    );
+    let depth = graph_builder.create_image(
+        window_kind,
+        1,
+        Format::D32Sfloat,
+        Some(ClearValue::DepthStencil(ClearDepthStencil(1.0, 0))),
+    );
+
+    let main_begin = add_timestamp(&mut graph_builder, profiling, Marker::MainBegin, &[]);
+    let mut main_subpass = SubpassBuilder::new()
+        .with_group(DrawShadedDesc::default().builder())
+        .with_color(color)
+        .with_depth_stencil(depth);
+    if let Some(node) = main_begin {
+        main_subpass.add_dependency(node);
+    }
+    let main_pass = graph_builder.add_node(main_subpass.into_pass());
+    let main_end = add_timestamp(&mut graph_builder, profiling, Marker::MainEnd, &[main_pass]);
+    // without post-processing the fxaa timestamps directly follow the main pass, keeping timings
+    // complete
+    let mut after: Vec<NodeId> = main_end.into_iter().collect();
+    if let Some(node) = add_timestamp(&mut graph_builder, profiling, Marker::FxaaBegin, &after) {
+        after = vec![node];
+    }
+    let post_end = add_timestamp(&mut graph_builder, profiling, Marker::FxaaEnd, &after);
+
+    let mut capture = crate::screenshot::CaptureDesc::unprocessed()
+        .builder()
+        .with_image(color)
+        .with_dependency(main_pass);
+    if let Some(node) = post_end {
+        capture.add_dependency(node);
+    }
+    let capture = graph_builder.add_node(capture);
+
+    if let Some(surface) = surface {
+        let _present = graph_builder
+            .add_node(PresentNode::builder(factory, surface, color).with_dependency(capture));
+    }
+
+    graph_builder
+}
+
+// size in pixels of a window with the given logical size, never smaller than a pixel
+pub fn physical_size(logical: (f64, f64), hidpi: f64) -> (u32, u32) {
+    let scale = |size: f64| ((size * hidpi).round() as u32).max(1);
+    (scale(logical.0), scale(logical.1))
+}
+
+// add a timestamp node running after the `after` nodes, if profiling is enabled
+fn add_timestamp(
+    graph_builder: &mut GraphBuilder<DefaultBackend, World>,
+    profiling: bool,
+    marker: Marker,
+    after: &[NodeId],
+) -> Option<NodeId> {
+    if !profiling {
+        return None;
+    }
+    let mut builder = TimestampDesc::new(marker).builder();
+    for &node in after {
+        builder.add_dependency(node);
+    }
+    Some(graph_builder.add_node(builder))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{physical_size, PostProcessFallback};
+    use crate::fullscreen::{FullscreenPassDesc, PassInfo};
+    use crate::postprocess::{chain_passes, PostEffect};
+    use amethyst::ecs::World;
+    use glsl_layout::vec4;
+
+    #[test]
+    fn physical_size_rounds_hidpi_sizes() {
+        assert_eq!(physical_size((1280.0, 720.0), 1.0), (1280, 720));
+        assert_eq!(physical_size((1280.0, 720.0), 1.5), (1920, 1080));
+        // 1279.5 and 721.5 round up
+        assert_eq!(physical_size((853.0, 481.0), 1.5), (1280, 722));
+        assert_eq!(physical_size((800.0, 600.0), 2.0), (1600, 1200));
+    }
+
+    #[test]
+    fn physical_size_is_at_least_a_pixel() {
+        assert_eq!(physical_size((0.0, 0.0), 2.0), (1, 1));
+        assert_eq!(physical_size((0.3, 100.0), 1.0), (1, 100));
+    }
+
+    #[test]
+    fn failed_pipelines_are_left_out_of_the_rebuilt_chain() {
+        let mut world = World::new();
+        world.insert(PostProcessFallback::default());
+
+        // a fragment shader the pipeline can't be built from, reported as the node build does
+        let broken = FullscreenPassDesc::new("Sharpen", vec![0u8; 4], (64, 64), |_: &World, _: &PassInfo| {
+            vec4::from([0.0; 4])
+        });
+        let err = broken.shaders().err().expect("the shader doesn't load");
+        PostProcessFallback::report(&world, broken.name(), &err);
+        PostProcessFallback::report(&world, broken.name(), &err);
+        let failed = world.fetch::<PostProcessFallback>().failed();
+        assert_eq!(failed, ["Sharpen"]);
+
+        let effects = [PostEffect::Fxaa, PostEffect::Sharpen];
+        let passes = chain_passes(&effects, None, None, false, (64, 64), &failed);
+        assert_eq!(passes.iter().map(|pass| pass.name()).collect::<Vec<_>>(), ["FXAA"]);
+    }
+}
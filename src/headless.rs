@@ -0,0 +1,31 @@
+// offscreen rendering without a window or swapchain
+// the render graph renders into an image of the configured size and reads it back through the
+// screenshot capture node, which makes it possible to run on software vulkan implementations
+
+use serde::{Deserialize, Serialize};
+
+/// Headless configuration, loaded from `config/headless.ron` and enabled with `--headless`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HeadlessConfig {
+    pub enabled: bool,
+    /// Size of the offscreen target.
+    pub width: u32,
+    pub height: u32,
+    /// Number of frames to render before exiting.
+    pub frames: u64,
+    /// Write the last rendered frame to the screenshot directory.
+    pub capture: bool,
+}
+
+impl Default for HeadlessConfig {
+    fn default() -> Self {
+        HeadlessConfig {
+            enabled: false,
+            width: 500,
+            height: 500,
+            frames: 60,
+            capture: true,
+        }
+    }
+}
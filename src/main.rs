@@ -1,9 +1,28 @@
+mod adaptive;
+mod benchmark;
+mod cli;
+mod color_grade;
+mod dither;
+mod fullscreen;
 mod fxaa;
+mod fxaa_cpu;
 mod graph;
+mod headless;
+mod mask;
+mod postprocess;
+mod profiling;
+mod quality;
+mod reflect;
+mod scale;
+mod scenes;
+mod screenshot;
+mod sharpen;
+mod ssaa;
+mod upscale;
 
 use amethyst::{
     assets::{
-        PrefabLoader, PrefabLoaderSystemDesc, RonFormat, PrefabData, ProgressCounter,
+        HotReloadBundle, PrefabLoader, PrefabLoaderSystemDesc, RonFormat, PrefabData, ProgressCounter,
         Processor,
     },
     core::{
@@ -11,7 +30,7 @@ use amethyst::{
         shrev::{EventChannel, ReaderId},
     },
     derive::{PrefabData},
-    ecs::{Entity, WorldExt},
+    ecs::{Entity, World, WorldExt},
     prelude::{
         Application, Builder, GameData, GameDataBuilder, SimpleState, SimpleTrans, StateData,
         StateEvent, Trans,
@@ -36,12 +55,24 @@ use amethyst::{
     controls::{ArcBallControlBundle, ControlTagPrefab},
     winit::VirtualKeyCode,
     Error,
-    window::{WindowBundle},
+    config::Config,
+    log,
+    window::{DisplayConfig, ScreenDimensions, Window, WindowBundle},
     derive::SystemDesc,
     ecs::prelude::{Read, Write, System, SystemData },
     input::{InputEvent},
 };
 use serde::{Deserialize, Serialize};
+use adaptive::{AdaptiveQualityConfig, AdaptiveQualitySystem};
+use benchmark::{Benchmark, BenchmarkConfig};
+use color_grade::{ColorGradeLut, ColorGradeSettings};
+use dither::DitherSettings;
+use headless::HeadlessConfig;
+use postprocess::{PostEffect, PostProcessChain};
+use profiling::{ProfilingConfig, ProfilingSystem};
+use scale::{DynamicResolutionSystem, RenderScaleConfig};
+use screenshot::{ScreenshotConfig, ScreenshotQueue};
+use ssaa::SsaaSettings;
 
 #[derive(Default, Deserialize, PrefabData, Serialize)]
 #[serde(default)]
@@ -52,46 +83,204 @@ struct ScenePrefab {
     camera: Option<CameraPrefab>,
     auto_fov: Option<AutoFov>,
     control_tag: Option<ControlTagPrefab>,
+    fxaa_exclude: Option<mask::FxaaExclude>,
+    rotate: Option<scenes::Rotate>,
 }
 
-#[derive(Default)]
-struct MainState;
+struct MainState {
+    // scene prefab loaded from the assets directory
+    scene: String,
+    // quit after rendering this many frames
+    frame_limit: Option<u64>,
+    // capture the last frame before quitting
+    capture_last: bool,
+    // capture the frame with this number and keep running
+    screenshot_after: Option<u64>,
+    // switch the window to fullscreen on start
+    fullscreen: bool,
+    // measure every fxaa mode and quit once the report is written
+    benchmark: Option<Benchmark>,
+    frame: u64,
+}
+
+impl Default for MainState {
+    fn default() -> Self {
+        MainState {
+            scene: "scene.ron".to_string(),
+            frame_limit: None,
+            capture_last: false,
+            screenshot_after: None,
+            fullscreen: false,
+            benchmark: None,
+            frame: 0,
+        }
+    }
+}
 
 impl SimpleState for MainState {
     fn on_start(&mut self, data: StateData<'_, GameData<'_, '_>>) {
-        // load the scene from the ron file
-        let handle = data.world.exec(|loader: PrefabLoader<'_, ScenePrefab>| {
-            loader.load("scene.ron", RonFormat, ())
-        });
-        data.world.create_entity().with(handle).build();
-
-        // set fxaa enabled
-        data.world.insert(FxaaSettings{ enabled:true });
+        if self.fullscreen {
+            if let Some(window) = data.world.try_fetch::<Window>() {
+                window.set_fullscreen(Some(window.get_current_monitor()));
+            }
+        }
+        self.load_scene(data.world);
+        color_grade::load_lut(data.world);
     }
 
-    fn handle_event(&mut self, _data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
-        if let StateEvent::Window(ref event) = event {
-            if is_close_requested(event) || is_key_down(event, VirtualKeyCode::Escape) {
-                Trans::Quit
-            } else {
+    fn handle_event(&mut self, data: StateData<'_, GameData<'_, '_>>, event: StateEvent) -> SimpleTrans {
+        match event {
+            StateEvent::Window(ref event) => {
+                if is_close_requested(event) || is_key_down(event, VirtualKeyCode::Escape) {
+                    Trans::Quit
+                } else {
+                    Trans::None
+                }
+            },
+            StateEvent::Input(InputEvent::ActionPressed(ref action)) if action == "next_scene" => {
+                // every entity belongs to the scene, so the world is cleared before loading the next
+                self.scene = scenes::next_scene(&self.scene).to_string();
+                data.world.delete_all();
+                self.load_scene(data.world);
                 Trans::None
+            },
+            _ => Trans::None,
+        }
+    }
+
+    fn update(&mut self, data: &mut StateData<'_, GameData<'_, '_>>) -> SimpleTrans {
+        self.frame += 1;
+        if self.screenshot_after == Some(self.frame) {
+            let both = data.world.try_fetch::<ScreenshotConfig>().map_or(false, |c| c.capture_both);
+            data.world.write_resource::<ScreenshotQueue>().request(both);
+        }
+        if let Some(ref mut benchmark) = self.benchmark {
+            if benchmark.update(data.world) {
+                return Trans::Quit;
             }
-        } else {
-            Trans::None
         }
+        match self.frame_limit {
+            Some(limit) if self.frame >= limit => {
+                // the frame is still rendered before the application quits
+                if self.capture_last {
+                    data.world.write_resource::<ScreenshotQueue>().request(false);
+                }
+                Trans::Quit
+            },
+            _ => Trans::None,
+        }
+    }
+}
+
+impl MainState {
+    fn load_scene(&self, world: &mut World) {
+        log::info!("Loading scene {}", self.scene);
+        // load the scene from the ron file
+        let handle = world.exec(|loader: PrefabLoader<'_, ScenePrefab>| {
+            loader.load(self.scene.as_str(), RonFormat, ())
+        });
+        world.create_entity().with(handle).build();
     }
 }
 
 fn main() -> amethyst::Result<()> {
     amethyst::start_logger(Default::default());
 
+    let options = cli::Options::parse(std::env::args().skip(1))?;
+    if options.help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    if let Some(ref quality_options) = options.quality {
+        return quality::run(quality_options);
+    }
+    if let Some(ref lut_options) = options.lut {
+        return color_grade::write_identity(lut_options);
+    }
+
     let app_root = application_root_dir()?;
 
     let assets_dir = app_root.join("assets");
     let config_dir = app_root.join("config");
-    let display_config_path = config_dir.join("display.ron");
+    let input_config_path = config_dir.join("input.ron");
+    let screenshot_config = ScreenshotConfig::load(config_dir.join("screenshot.ron"))?;
+    let mut headless_config = HeadlessConfig::load(config_dir.join("headless.ron"))?;
+    let mut profiling_config = ProfilingConfig::load(config_dir.join("profiling.ron"))?;
+    let mut render_scale_config = RenderScaleConfig::load(config_dir.join("render_scale.ron"))?;
+    let mut display_config = DisplayConfig::load(config_dir.join("display.ron"))?;
+    let mut fxaa_settings = FxaaSettings::load(config_dir.join("fxaa.ron"))?;
+    let mut adaptive_config = AdaptiveQualityConfig::load(config_dir.join("adaptive_quality.ron"))?;
+    if adaptive_config.enabled && !profiling_config.enabled {
+        log::info!("Adaptive FXAA quality measures the passes with the profiling timestamps, enabling them");
+        profiling_config.enabled = true;
+    }
+    if fxaa_settings.alpha_mode() != fxaa_settings.alpha {
+        log::warn!("Luma is read from the alpha channel, FXAA output will be opaque");
+    }
+    let mut benchmark_config = BenchmarkConfig::load(config_dir.join("benchmark.ron"))?;
+    let mut ssaa_settings = SsaaSettings::load(config_dir.join("ssaa.ron"))?;
+    let mut post_process_chain = PostProcessChain::load(config_dir.join("postprocess.ron"))?;
+    let color_grade_settings = ColorGradeSettings::load(config_dir.join("color_grade.ron"))?;
+    let dither_settings = DitherSettings::load(config_dir.join("dither.ron"))?;
+
+    // command line options take precedence over the config files
+    headless_config.enabled |= options.headless;
+    benchmark_config.enabled |= options.benchmark;
+    if let Some((width, height)) = options.size {
+        display_config.dimensions = Some((width, height));
+        headless_config.width = width;
+        headless_config.height = height;
+    }
+    match options.anti_aliasing {
+        Some(cli::AntiAliasing::Off) => {
+            fxaa_settings.enabled = false;
+            ssaa_settings.enabled = false;
+            post_process_chain.set_enabled(PostEffect::Sharpen, false);
+        },
+        Some(cli::AntiAliasing::Fxaa(quality)) => {
+            fxaa_settings.enabled = true;
+            fxaa_settings.quality = quality;
+            ssaa_settings.enabled = false;
+            post_process_chain.set_enabled(PostEffect::Fxaa, true);
+            post_process_chain.set_enabled(PostEffect::Sharpen, false);
+        },
+        Some(cli::AntiAliasing::FxaaSharpen(quality)) => {
+            fxaa_settings.enabled = true;
+            fxaa_settings.quality = quality;
+            ssaa_settings.enabled = false;
+            post_process_chain.set_enabled(PostEffect::Fxaa, true);
+            post_process_chain.set_enabled(PostEffect::Sharpen, true);
+        },
+        Some(cli::AntiAliasing::Ssaa(factor)) => {
+            ssaa_settings.enabled = true;
+            ssaa_settings.factor = factor;
+        },
+        None => (),
+    }
+    if let Some(factor) = ssaa_settings.active_factor() {
+        log::info!("Supersampling at {}x{} with a {:?} filter", factor, factor, ssaa_settings.filter);
+    }
+    let post_effects: Vec<&str> = post_process_chain
+        .passes
+        .iter()
+        .filter(|pass| pass.enabled)
+        .map(|pass| pass.effect.name())
+        .collect();
+    log::info!("Post-processing chain: [{}]", post_effects.join(", "));
+    if let Some(debug_view) = options.debug_view {
+        fxaa_settings.debug_view = debug_view;
+    }
+    if let Some(ref scene) = options.scene {
+        benchmark_config.scene = scenes::scene_path(scene);
+    }
+    if benchmark_config.enabled {
+        // every mode is measured at the same resolution and quality
+        adaptive_config.enabled = false;
+        render_scale_config.dynamic = false;
+        profiling_config.enabled = true;
+    }
 
-    let game_data = GameDataBuilder::default()
+    let mut game_data = GameDataBuilder::default()
         .with_system_desc(
             PrefabLoaderSystemDesc::<ScenePrefab>::default(), 
             "scene_loader", 
@@ -100,10 +289,14 @@ fn main() -> amethyst::Result<()> {
         .with_bundle(TransformBundle::new())?
         .with(AutoFovSystem::new(), "auto_fov", &["scene_loader"])
         .with_bundle(
-            InputBundle::<StringBindings>::new(),
+            InputBundle::<StringBindings>::new().with_bindings_from_file(input_config_path)?,
         )?
         .with_bundle(ArcBallControlBundle::<StringBindings>::new())?
         .with_system_desc(FxaaSystemDesc::default(), "fxaa", &["input_system"])
+        .with(ProfilingSystem::default(), "profiling", &[])
+        .with(DynamicResolutionSystem::default(), "dynamic_resolution", &[])
+        .with(AdaptiveQualitySystem::default(), "adaptive_quality", &["fxaa"])
+        .with(scenes::RotateSystem, "rotate", &[])
         .with(
             VisibilitySortingSystem::new(),
             "visibility_sorting_system",
@@ -119,52 +312,212 @@ fn main() -> amethyst::Result<()> {
             "texture_processor",
             &[],
         )
-        .with(Processor::<Material>::new(), "material_processor", &[])
-        .with_bundle(WindowBundle::from_config_path(display_config_path)?)?
-        .with_thread_local(RenderingSystem::<DefaultBackend, _>::new(
-            graph::RenderGraph::default(),
+        .with(Processor::<Material>::new(), "material_processor", &[]);
+    if color_grade_settings.hot_reload {
+        game_data = game_data.with_bundle(HotReloadBundle::default())?;
+    }
+
+    // without a window there is no event loop and the screen dimensions are those of the
+    // offscreen target
+    let mut state;
+    if headless_config.enabled {
+        state = MainState {
+            frame_limit: Some(headless_config.frames),
+            capture_last: headless_config.capture,
+            ..Default::default()
+        };
+        game_data = game_data.with_thread_local(RenderingSystem::<DefaultBackend, _>::new(
+            graph::RenderGraph::headless(),
         ));
+    } else {
+        state = MainState::default();
+        game_data = game_data
+            .with_bundle(WindowBundle::from_config(display_config))?
+            .with_thread_local(RenderingSystem::<DefaultBackend, _>::new(
+                graph::RenderGraph::default(),
+            ));
+    }
 
-    let mut game = Application::new(assets_dir, MainState::default(), game_data)?;
+    if let Some(ref scene) = options.scene {
+        state.scene = scenes::scene_path(scene);
+    }
+    if options.frames.is_some() {
+        state.frame_limit = options.frames;
+    }
+    state.screenshot_after = options.screenshot_after;
+    state.fullscreen = options.fullscreen;
+    if benchmark_config.enabled {
+        // the benchmark decides when to quit
+        state.frame_limit = None;
+        state.capture_last = false;
+        state.scene = benchmark_config.scene.clone();
+        state.benchmark = Some(Benchmark::new(benchmark_config));
+    }
+
+    let mut app_builder = Application::build(assets_dir, state)?
+        .with_resource(fxaa_settings)
+        .with_resource(fxaa::FxaaStats::default())
+        .with_resource(ssaa_settings)
+        .with_resource(post_process_chain)
+        .with_resource(color_grade_settings)
+        .with_resource(ColorGradeLut::default())
+        .with_resource(dither_settings)
+        .with_resource(graph::PostProcessFallback::default())
+        .with_resource(screenshot_config)
+        .with_resource(profiling_config)
+        .with_resource(adaptive_config)
+        .with_resource(render_scale_config.initial_scale())
+        .with_resource(render_scale_config);
+    if headless_config.enabled {
+        app_builder = app_builder.with_resource(ScreenDimensions::new(
+            headless_config.width,
+            headless_config.height,
+            1.0,
+        ));
+    }
+    let mut game = app_builder.build(game_data)?;
     game.run();
 
     Ok(())
 }
 
-// resource to keep track if fxaa is enabled
-#[derive(Default)]
+// resource to keep track of the fxaa settings, loaded from config/fxaa.ron
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
 pub struct FxaaSettings {
     pub enabled: bool,
+    // how luma is computed for edge detection
+    pub luma: fxaa::LumaMode,
+    // render the main pass into an sRGB image, so fxaa filters in linear space
+    pub srgb_input: bool,
+    // combine depth discontinuities with the luma edge test
+    pub depth_edges: bool,
+    // depth difference relative to the pixel's view distance that counts as an edge
+    pub depth_threshold: f32,
+    // what is written to the alpha channel of the output
+    pub alpha: fxaa::AlphaMode,
+    // count edge statistics into the FxaaStats resource
+    pub stats: bool,
+    // quality preset, adjusted at runtime when adaptive quality is enabled
+    pub quality: fxaa::FxaaQuality,
+    // show a diagnostic view instead of the anti-aliased image
+    pub debug_view: fxaa::DebugView,
+    // strength of the sharpening pass from 0.0 to 1.0, when it is enabled in the post-processing chain
+    pub sharpen_strength: f32,
+}
+
+impl Default for FxaaSettings {
+    fn default() -> Self {
+        FxaaSettings {
+            enabled: true,
+            luma: fxaa::LumaMode::default(),
+            srgb_input: false,
+            depth_edges: false,
+            depth_threshold: 0.05,
+            alpha: fxaa::AlphaMode::default(),
+            stats: false,
+            quality: fxaa::FxaaQuality::default(),
+            debug_view: fxaa::DebugView::default(),
+            sharpen_strength: 0.5,
+        }
+    }
 }
 
-// simple system to toggle fxaa
+impl FxaaSettings {
+    // alpha mode in effect, alpha can't be passed through when it holds the luma
+    pub fn alpha_mode(&self) -> fxaa::AlphaMode {
+        if self.luma == fxaa::LumaMode::Alpha {
+            fxaa::AlphaMode::Opaque
+        } else {
+            self.alpha
+        }
+    }
+}
+
+// commands that can be sent to the fxaa system through an EventChannel<FxaaCommand>
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FxaaCommand {
+    Toggle,
+    // switch between the supersampled reference and the configured anti-aliasing
+    ToggleSsaa,
+    // add or remove the sharpening pass after fxaa, rebuilding the graph
+    ToggleSharpen,
+    // capture the next frame, writing the aliased image as well if configured
+    Screenshot,
+    // capture the next frame both before and after fxaa
+    ScreenshotBoth,
+}
+
+// simple system to toggle fxaa and take screenshots
 #[derive(SystemDesc)]
 #[system_desc(name(FxaaSystemDesc))]
 pub struct FxaaSystem {
     #[system_desc(event_channel_reader)]
     event_reader: ReaderId<InputEvent<StringBindings>>,
+    #[system_desc(event_channel_reader)]
+    command_reader: ReaderId<FxaaCommand>,
 }
 
 impl FxaaSystem {
-    pub fn new(event_reader: ReaderId<InputEvent<StringBindings>>) -> Self {
-        Self { event_reader:event_reader }
+    pub fn new(
+        event_reader: ReaderId<InputEvent<StringBindings>>,
+        command_reader: ReaderId<FxaaCommand>,
+    ) -> Self {
+        Self { event_reader:event_reader, command_reader:command_reader }
     }
 }
 
 impl<'s> System<'s> for FxaaSystem {
     type SystemData = (
         Read<'s, EventChannel<InputEvent<StringBindings>>>,
+        Read<'s, EventChannel<FxaaCommand>>,
         Write<'s, FxaaSettings>,
+        Write<'s, SsaaSettings>,
+        Write<'s, PostProcessChain>,
+        Write<'s, ScreenshotQueue>,
+        Option<Read<'s, ScreenshotConfig>>,
     );
 
-    fn run(&mut self, (events,mut fxaa_settings): Self::SystemData) {
+    fn run(&mut self, (events,commands,mut fxaa_settings,mut ssaa_settings,mut chain,mut screenshots,screenshot_config): Self::SystemData) {
+        let mut pending:Vec<FxaaCommand> = commands.read(&mut self.command_reader).cloned().collect();
         for event in events.read(&mut self.event_reader) {
-            match event {
-                InputEvent::KeyPressed { key_code:VirtualKeyCode::F, .. } => {
+            if let InputEvent::ActionPressed(action) = event {
+                match action.as_str() {
+                    "toggle_fxaa" => pending.push(FxaaCommand::Toggle),
+                    "toggle_ssaa" => pending.push(FxaaCommand::ToggleSsaa),
+                    "toggle_sharpen" => pending.push(FxaaCommand::ToggleSharpen),
+                    "screenshot" => pending.push(FxaaCommand::Screenshot),
+                    "screenshot_both" => pending.push(FxaaCommand::ScreenshotBoth),
+                    _ => (),
+                };
+            }
+        }
+
+        for command in pending {
+            match command {
+                FxaaCommand::Toggle => {
                     fxaa_settings.enabled = !fxaa_settings.enabled;
                 },
-                _ => (),
+                FxaaCommand::ToggleSsaa => {
+                    ssaa_settings.enabled = !ssaa_settings.enabled;
+                    match ssaa_settings.active_factor() {
+                        Some(factor) => log::info!("Supersampling at {}x{}", factor, factor),
+                        None => log::info!("Supersampling off"),
+                    }
+                },
+                FxaaCommand::ToggleSharpen => {
+                    let enabled = !chain.is_enabled(&PostEffect::Sharpen);
+                    chain.set_enabled(PostEffect::Sharpen, enabled);
+                    log::info!("Sharpening {}", if enabled { "on" } else { "off" });
+                },
+                FxaaCommand::Screenshot => {
+                    let both = screenshot_config.as_ref().map_or(false, |c| c.capture_both);
+                    screenshots.request(both);
+                },
+                FxaaCommand::ScreenshotBoth => {
+                    screenshots.request(true);
+                },
             };
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,235 @@
+// fxaa exclusion mask
+// entities marked with FxaaExclude are drawn into an R8 mask after the main pass, tested against
+// its depth buffer so only their visible parts are flagged, the fxaa pass leaves flagged pixels alone
+
+use amethyst::{
+    assets::{AssetStorage, Handle, PrefabData},
+    core::{Hidden, Transform},
+    derive::PrefabData,
+    ecs::{
+        prelude::{Component, Entity, Join, NullStorage, Read, ReadStorage, SystemData, WriteStorage},
+        World,
+    },
+    renderer::{
+        pipeline::{PipelineDescBuilder, PipelinesBuilder},
+        pod::ViewArgs,
+        rendy::{
+            command::{QueueId, RenderPassEncoder},
+            factory::Factory,
+            graph::{
+                render::{PrepareResult, RenderGroup, RenderGroupDesc},
+                GraphContext, NodeBuffer, NodeImage,
+            },
+            hal::{self, device::Device, pso},
+            mesh::{AsVertex, Position},
+            shader::{Shader, SpirvShader},
+        },
+        submodules::{gather::CameraGatherer, DynamicUniform},
+        types::{Backend, Mesh},
+        util,
+    },
+};
+use glsl_layout::AsStd140;
+use serde::{Deserialize, Serialize};
+
+lazy_static::lazy_static! {
+    static ref VERTEX:SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../assets/shader/mask.vert.spv"),
+        pso::ShaderStageFlags::VERTEX,
+        "main",
+    ).unwrap();
+
+    static ref FRAGMENT:SpirvShader = SpirvShader::from_bytes(
+        include_bytes!("../assets/shader/mask.frag.spv"),
+        pso::ShaderStageFlags::FRAGMENT,
+        "main",
+    ).unwrap();
+}
+
+/// Marks an entity whose pixels should be passed through fxaa untouched, e.g. text or pixel art.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct FxaaExclude;
+
+impl Component for FxaaExclude {
+    type Storage = NullStorage<Self>;
+}
+
+// size of the model matrix pushed per draw
+const MODEL_SIZE: u32 = 16 * 4;
+
+/// Render group drawing the meshes of excluded entities into the mask.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DrawMaskDesc;
+
+#[derive(Debug)]
+pub struct DrawMask<B: Backend> {
+    pipeline: B::GraphicsPipeline,
+    pipeline_layout: B::PipelineLayout,
+    env: DynamicUniform<B, ViewArgs>,
+    // meshes and model matrices collected in prepare
+    draws: Vec<(Handle<Mesh>, [[f32; 4]; 4])>,
+}
+
+impl<B: Backend> RenderGroupDesc<B, World> for DrawMaskDesc {
+    fn build(
+        self,
+        _ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        _queue: QueueId,
+        _world: &World,
+        framebuffer_width: u32,
+        framebuffer_height: u32,
+        subpass: hal::pass::Subpass<'_, B>,
+        _buffers: Vec<NodeBuffer>,
+        _images: Vec<NodeImage>,
+    ) -> Result<Box<dyn RenderGroup<B, World>>, failure::Error> {
+        let env = DynamicUniform::new(factory, pso::ShaderStageFlags::VERTEX)?;
+        let (pipeline, pipeline_layout) = build_mask_pipeline(
+            factory,
+            subpass,
+            framebuffer_width,
+            framebuffer_height,
+            vec![env.raw_layout()],
+        )?;
+
+        Ok(Box::new(DrawMask::<B> {
+            pipeline,
+            pipeline_layout,
+            env,
+            draws: Vec::new(),
+        }))
+    }
+}
+
+impl<B: Backend> RenderGroup<B, World> for DrawMask<B> {
+    fn prepare(
+        &mut self,
+        factory: &Factory<B>,
+        _queue: QueueId,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) -> PrepareResult {
+        let (meshes, transforms, excluded, hidden) = <(
+            ReadStorage<'_, Handle<Mesh>>,
+            ReadStorage<'_, Transform>,
+            ReadStorage<'_, FxaaExclude>,
+            ReadStorage<'_, Hidden>,
+        )>::fetch(world);
+
+        let projview = CameraGatherer::gather(world).projview;
+        self.env.write(factory, index, projview.std140());
+
+        self.draws.clear();
+        for (mesh, transform, _, _) in (&meshes, &transforms, &excluded, !&hidden).join() {
+            self.draws
+                .push((mesh.clone(), (*transform.global_matrix()).into()));
+        }
+
+        PrepareResult::DrawRecord
+    }
+
+    fn draw_inline(
+        &mut self,
+        mut encoder: RenderPassEncoder<'_, B>,
+        index: usize,
+        _subpass: hal::pass::Subpass<'_, B>,
+        world: &World,
+    ) {
+        if self.draws.is_empty() {
+            return;
+        }
+
+        let mesh_storage = <Read<'_, AssetStorage<Mesh>>>::fetch(world);
+        encoder.bind_graphics_pipeline(&self.pipeline);
+        self.env.bind(index, &self.pipeline_layout, 0, &mut encoder);
+
+        for (handle, model) in &self.draws {
+            let mesh = match mesh_storage.get(handle).and_then(B::unwrap_mesh) {
+                Some(mesh) => mesh,
+                None => continue,
+            };
+            unsafe {
+                encoder.push_constants(
+                    &self.pipeline_layout,
+                    pso::ShaderStageFlags::VERTEX,
+                    0,
+                    hal::memory::cast_slice::<f32, u32>(&model.concat()),
+                );
+            }
+            if mesh
+                .bind_and_draw(0, &[Position::vertex()], 0..1, &mut encoder)
+                .is_err()
+            {
+                amethyst::log::warn!("Mesh without positions can't be drawn into the fxaa mask");
+            }
+        }
+    }
+
+    fn dispose(self: Box<Self>, factory: &mut Factory<B>, _world: &World) {
+        unsafe {
+            factory.device().destroy_graphics_pipeline(self.pipeline);
+            factory
+                .device()
+                .destroy_pipeline_layout(self.pipeline_layout);
+        }
+    }
+}
+
+fn build_mask_pipeline<B: Backend>(
+    factory: &Factory<B>,
+    subpass: hal::pass::Subpass<'_, B>,
+    framebuffer_width: u32,
+    framebuffer_height: u32,
+    layouts: Vec<&B::DescriptorSetLayout>,
+) -> Result<(B::GraphicsPipeline, B::PipelineLayout), failure::Error> {
+    let pipeline_layout = unsafe {
+        factory.device().create_pipeline_layout(
+            layouts,
+            Some((pso::ShaderStageFlags::VERTEX, 0..MODEL_SIZE)),
+        )
+    }?;
+
+    let shader_vertex = unsafe { VERTEX.module(factory).unwrap() };
+    let shader_fragment = unsafe { FRAGMENT.module(factory).unwrap() };
+
+    // the depth buffer of the main pass is only tested against, so hidden parts stay unmasked
+    let pipes = PipelinesBuilder::new()
+        .with_pipeline(
+            PipelineDescBuilder::new()
+                .with_vertex_desc(&[(Position::vertex(), pso::VertexInputRate::Vertex)])
+                .with_input_assembler(pso::InputAssemblerDesc::new(hal::Primitive::TriangleList))
+                .with_shaders(util::simple_shader_set(
+                    &shader_vertex,
+                    Some(&shader_fragment),
+                ))
+                .with_layout(&pipeline_layout)
+                .with_subpass(subpass)
+                .with_framebuffer_size(framebuffer_width, framebuffer_height)
+                .with_depth_test(pso::DepthTest {
+                    fun: pso::Comparison::LessEqual,
+                    write: false,
+                })
+                .with_blend_targets(vec![pso::ColorBlendDesc {
+                    mask: pso::ColorMask::ALL,
+                    blend: None,
+                }]),
+        )
+        .build(factory, None);
+
+    unsafe {
+        factory.destroy_shader_module(shader_vertex);
+        factory.destroy_shader_module(shader_fragment);
+    }
+
+    match pipes {
+        Err(e) => {
+            unsafe {
+                factory.device().destroy_pipeline_layout(pipeline_layout);
+            }
+            Err(e)
+        }
+        Ok(mut pipes) => Ok((pipes.remove(0), pipeline_layout)),
+    }
+}
@@ -0,0 +1,340 @@
+// post-processing chain
+// the ordered list of full-screen passes between the main pass and the presented image, the graph
+// is built from it and rebuilt when it changes
+
+use amethyst::{core::Time, ecs::World, log, utils::application_root_dir};
+use crate::color_grade::ColorGradeArgs;
+use crate::dither::DitherArgs;
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, PassInfo};
+use crate::fxaa::FXAAUniformArgs;
+use crate::sharpen::SharpenArgs;
+use crate::ssaa::DownsampleArgs;
+use crate::upscale::{EasuArgs, RcasArgs};
+use failure::ResultExt;
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+// pass through, used when the chain is empty and as template for custom passes
+const COPY_SPIRV: &[u8] = include_bytes!("../assets/shader/copy.frag.spv");
+
+/// Effect of a pass in the chain.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PostEffect {
+    Fxaa,
+    /// Contrast adaptive sharpening, see `FxaaSettings::sharpen_strength`.
+    Sharpen,
+    /// 3D lookup table color grading, see `ColorGradeSettings`.
+    ColorGrade,
+    /// Dithering and film grain against banding, see `DitherSettings`. Always runs last.
+    Dither,
+    /// Fragment shader compiled from a copy of `copy.frag`, with the same uniform and input.
+    /// `shader` is the SPIR-V file relative to the assets directory.
+    Custom { name: String, shader: String },
+}
+
+impl PostEffect {
+    pub fn name(&self) -> &str {
+        match self {
+            PostEffect::Fxaa => "FXAA",
+            PostEffect::Sharpen => "Sharpen",
+            PostEffect::ColorGrade => "Color grading",
+            PostEffect::Dither => "Dither",
+            PostEffect::Custom { name, .. } => name.as_str(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PostPass {
+    pub effect: PostEffect,
+    #[serde(default = "enabled_default")]
+    pub enabled: bool,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+/// Resource listing the post-processing passes in order, loaded from `config/postprocess.ron`.
+/// Reordering, adding or disabling passes at runtime rebuilds the graph.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PostProcessChain {
+    pub passes: Vec<PostPass>,
+}
+
+impl Default for PostProcessChain {
+    fn default() -> Self {
+        PostProcessChain {
+            passes: vec![PostPass {
+                effect: PostEffect::Fxaa,
+                enabled: true,
+            }],
+        }
+    }
+}
+
+impl PostProcessChain {
+    /// Effects of the enabled passes, in order.
+    pub fn effects(&self) -> Vec<PostEffect> {
+        self.passes
+            .iter()
+            .filter(|pass| pass.enabled)
+            .map(|pass| pass.effect.clone())
+            .collect()
+    }
+
+    pub fn is_enabled(&self, effect: &PostEffect) -> bool {
+        self.passes.iter().any(|pass| pass.enabled && pass.effect == *effect)
+    }
+
+    /// Enable or disable the passes with the effect, an effect missing from the chain is appended
+    /// when enabled.
+    pub fn set_enabled(&mut self, effect: PostEffect, enabled: bool) {
+        let mut found = false;
+        for pass in self.passes.iter_mut().filter(|pass| pass.effect == effect) {
+            pass.enabled = enabled;
+            found = true;
+        }
+        if !found && enabled {
+            self.passes.push(PostPass { effect, enabled });
+        }
+    }
+}
+
+/// layout(std140, set = 0, binding = 0) uniform PostArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform float time;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct PostArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    // seconds since the start, for animated effects
+    pub time: float,
+}
+
+// pass sampling only the previous image, with the PostArgs uniform
+fn color_pass<N, S>(name: N, fragment: S, target_size: (u32, u32)) -> FullscreenPassDesc<PostArgs>
+where
+    N: Into<Cow<'static, str>>,
+    S: Into<Cow<'static, [u8]>>,
+{
+    let uniform_args = |world: &World, info: &PassInfo| {
+        let (source_size, target_size) = info.sizes();
+        PostArgs {
+            source_size,
+            target_size,
+            time: world.try_fetch::<Time>().map_or(0.0, |time| time.absolute_time_seconds() as f32),
+        }
+    };
+    FullscreenPassDesc::new(name, fragment, target_size, uniform_args)
+        .with_input(FullscreenInput::linear())
+        .with_uniform_layout(crate::uniform_layout!(PostArgs { source_size, target_size, time }))
+}
+
+/// A pass of the chain, ready to be added to a graph.
+#[derive(Debug)]
+pub enum ChainPass {
+    Downsample(FullscreenPassDesc<DownsampleArgs>),
+    /// Also samples the depth buffer and the exclusion mask.
+    Fxaa(FullscreenPassDesc<FXAAUniformArgs>),
+    Sharpen(FullscreenPassDesc<SharpenArgs>),
+    ColorGrade(FullscreenPassDesc<ColorGradeArgs>),
+    Dither(FullscreenPassDesc<DitherArgs>),
+    Easu(FullscreenPassDesc<EasuArgs>),
+    Rcas(FullscreenPassDesc<RcasArgs>),
+    Color(FullscreenPassDesc<PostArgs>),
+}
+
+impl ChainPass {
+    pub fn name(&self) -> &str {
+        match self {
+            ChainPass::Downsample(desc) => desc.name(),
+            ChainPass::Fxaa(desc) => desc.name(),
+            ChainPass::Sharpen(desc) => desc.name(),
+            ChainPass::ColorGrade(desc) => desc.name(),
+            ChainPass::Dither(desc) => desc.name(),
+            ChainPass::Easu(desc) => desc.name(),
+            ChainPass::Rcas(desc) => desc.name(),
+            ChainPass::Color(desc) => desc.name(),
+        }
+    }
+
+    /// Size of the image the pass writes.
+    pub fn target_size(&self) -> (u32, u32) {
+        match self {
+            ChainPass::Downsample(desc) => desc.target_size(),
+            ChainPass::Fxaa(desc) => desc.target_size(),
+            ChainPass::Sharpen(desc) => desc.target_size(),
+            ChainPass::ColorGrade(desc) => desc.target_size(),
+            ChainPass::Dither(desc) => desc.target_size(),
+            ChainPass::Easu(desc) => desc.target_size(),
+            ChainPass::Rcas(desc) => desc.target_size(),
+            ChainPass::Color(desc) => desc.target_size(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), failure::Error> {
+        match self {
+            ChainPass::Downsample(desc) => desc.validate(),
+            ChainPass::Fxaa(desc) => desc.validate(),
+            ChainPass::Sharpen(desc) => desc.validate(),
+            ChainPass::ColorGrade(desc) => desc.validate(),
+            ChainPass::Dither(desc) => desc.validate(),
+            ChainPass::Easu(desc) => desc.validate(),
+            ChainPass::Rcas(desc) => desc.validate(),
+            ChainPass::Color(desc) => desc.validate(),
+        }
+    }
+}
+
+/// Passes writing images of `target_size` from the main pass, in order. With supersampling the
+/// downsample pass comes first and replaces fxaa, an empty chain copies the main pass. With
+/// `upscale_from`, the render size, the passes up to fxaa run at that size and are followed by the
+/// upscaling and its sharpening, or the upscaling comes first without fxaa. `fxaa_stats` selects
+/// the fxaa variant counting edge statistics. The passes named in `excluded` are left out, all of
+/// the upscaling without EASU, and the chain is empty if even the copy is. So are the passes whose
+/// shaders can't be loaded or don't match, with a warning. Dithering is moved to the end.
+pub fn chain_passes(
+    effects: &[PostEffect],
+    ssaa: Option<u32>,
+    upscale_from: Option<(u32, u32)>,
+    fxaa_stats: bool,
+    target_size: (u32, u32),
+    excluded: &[String],
+) -> Vec<ChainPass> {
+    // the chain is built again without a broken pass, as leaving out fxaa or EASU moves the upscaling
+    let without = |pass: &str, err: failure::Error| {
+        let causes: Vec<String> = err.iter_chain().map(|cause| cause.to_string()).collect();
+        log::warn!("{} pass left out of the chain: {}", pass, causes.join(": "));
+        let mut excluded = excluded.to_vec();
+        excluded.push(pass.to_string());
+        chain_passes(effects, ssaa, upscale_from, fxaa_stats, target_size, &excluded)
+    };
+    let is_excluded = |name: &str| excluded.iter().any(|excluded| excluded == name);
+    let mut enabled = effects.iter().filter(|effect| !is_excluded(effect.name())).collect::<Vec<_>>();
+    // dithering must come right before the output is quantized, anything after it would filter the
+    // noise away, so it runs last wherever it is in the chain
+    enabled.sort_by_key(|effect| **effect == PostEffect::Dither);
+    let upscale_source = upscale_from.filter(|_| !is_excluded("EASU"));
+
+    let mut passes = Vec::new();
+    if let Some(factor) = ssaa {
+        passes.push(ChainPass::Downsample(crate::ssaa::pass(target_size, factor)));
+    }
+    let upscale = |passes: &mut Vec<ChainPass>| {
+        passes.push(ChainPass::Easu(crate::upscale::easu_pass(target_size)));
+        passes.push(ChainPass::Rcas(crate::upscale::rcas_pass(target_size)));
+    };
+    let upscale_after =
+        upscale_source.and_then(|_| enabled.iter().position(|effect| **effect == PostEffect::Fxaa));
+    let mut size = match (upscale_source, upscale_after) {
+        (Some(render_size), Some(_)) => render_size,
+        (Some(_), None) => {
+            upscale(&mut passes);
+            target_size
+        }
+        (None, _) => target_size,
+    };
+    for (index, effect) in enabled.into_iter().enumerate() {
+        let pass = match effect {
+            PostEffect::Fxaa if ssaa.is_some() => continue,
+            PostEffect::Fxaa => ChainPass::Fxaa(crate::fxaa::pass(size, fxaa_stats)),
+            PostEffect::Sharpen => ChainPass::Sharpen(crate::sharpen::pass(size)),
+            PostEffect::ColorGrade => ChainPass::ColorGrade(crate::color_grade::pass(size)),
+            PostEffect::Dither => ChainPass::Dither(crate::dither::pass(size)),
+            PostEffect::Custom { name, shader } => {
+                match read_shader(shader) {
+                    Ok(spirv) => ChainPass::Color(color_pass(name.clone(), spirv, size)),
+                    Err(err) => return without(name, err),
+                }
+            }
+        };
+        passes.push(pass);
+        if upscale_after == Some(index) {
+            upscale(&mut passes);
+            size = target_size;
+        }
+    }
+    passes.retain(|pass| !is_excluded(pass.name()));
+    if passes.is_empty() && !is_excluded("Copy") {
+        passes.push(ChainPass::Color(color_pass("Copy", COPY_SPIRV, target_size)));
+    }
+
+    for pass in &passes {
+        if let Err(err) = pass.validate() {
+            return without(pass.name(), err);
+        }
+    }
+    passes
+}
+
+// SPIR-V of a custom pass, relative to the assets directory
+fn read_shader(shader: &str) -> Result<Vec<u8>, failure::Error> {
+    let path = application_root_dir()?.join("assets").join(shader);
+    Ok(std::fs::read(&path).with_context(|_| format!("Failed to read {}", path.display()))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(passes: &[ChainPass]) -> Vec<&str> {
+        passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    #[test]
+    fn copy_matches_its_shader() {
+        color_pass("Copy", COPY_SPIRV, (64, 64)).validate().unwrap();
+    }
+
+    #[test]
+    fn dither_runs_last() {
+        let effects = [PostEffect::Dither, PostEffect::Fxaa, PostEffect::ColorGrade];
+        let passes = chain_passes(&effects, None, None, false, (64, 64), &[]);
+        assert_eq!(names(&passes), ["FXAA", "Color grading", "Dither"]);
+
+        // after the upscaling too, at the output size
+        let passes = chain_passes(&effects, None, Some((32, 32)), false, (64, 64), &[]);
+        assert_eq!(names(&passes), ["FXAA", "EASU", "RCAS", "Color grading", "Dither"]);
+        assert_eq!(passes[0].target_size(), (32, 32));
+        assert_eq!(passes[4].target_size(), (64, 64));
+    }
+
+    #[test]
+    fn broken_passes_are_left_out() {
+        let effects = [
+            PostEffect::Fxaa,
+            PostEffect::Custom {
+                name: "Vignette".to_string(),
+                shader: "shader/missing.frag.spv".to_string(),
+            },
+            PostEffect::Sharpen,
+        ];
+        let passes = chain_passes(&effects, None, None, false, (64, 64), &[]);
+        assert_eq!(names(&passes), ["FXAA", "Sharpen"]);
+    }
+
+    #[test]
+    fn excluded_passes_are_left_out() {
+        let effects = [PostEffect::Fxaa, PostEffect::Sharpen];
+        let excluded = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+
+        // without fxaa the upscaling reads the main pass
+        let passes = chain_passes(&effects, None, Some((32, 32)), false, (64, 64), &excluded(&["FXAA"]));
+        assert_eq!(names(&passes), ["EASU", "RCAS", "Sharpen"]);
+        // without EASU there's no upscaling at all
+        let passes = chain_passes(&effects, None, Some((32, 32)), false, (64, 64), &excluded(&["EASU"]));
+        assert_eq!(names(&passes), ["FXAA", "Sharpen"]);
+        assert!(passes.iter().all(|pass| pass.target_size() == (64, 64)));
+
+        let passes = chain_passes(&effects, None, None, false, (64, 64), &excluded(&["FXAA", "Sharpen"]));
+        assert_eq!(names(&passes), ["Copy"]);
+        let passes = chain_passes(&effects, None, None, false, (64, 64), &excluded(&["FXAA", "Sharpen", "Copy"]));
+        assert!(passes.is_empty());
+    }
+}
@@ -0,0 +1,418 @@
+// gpu pass profiling
+// timestamp nodes are inserted around the main and the anti-aliasing pass, their queries are read
+// back a few frames later and collected into the PassTimings resource
+
+use amethyst::{
+    core::Time,
+    ecs::{prelude::{Read, System, SystemData, Write}, World},
+    log,
+};
+use rendy::{
+    command::{
+        CommandBuffer, CommandPool, ExecutableState, Family, Fence, IndividualReset, MultiShot,
+        PendingState, PrimaryLevel, Queue, QueueType, SimultaneousUse, Submission, Submit,
+    },
+    factory::Factory,
+    frame::Frames,
+    graph::{GraphContext, Node, NodeBuffer, NodeDesc, NodeImage},
+    hal::{self, command::CommandBuffer as _, device::Device},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{BufWriter, Write as _};
+use std::path::PathBuf;
+
+/// Profiling configuration, loaded from `config/profiling.ron`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ProfilingConfig {
+    pub enabled: bool,
+    /// Number of frames the rolling statistics are computed over.
+    pub samples: usize,
+    /// Seconds between log entries, 0 disables logging.
+    pub log_interval: f32,
+    /// File every resolved frame is appended to as csv.
+    pub csv: Option<PathBuf>,
+}
+
+impl Default for ProfilingConfig {
+    fn default() -> Self {
+        ProfilingConfig {
+            enabled: false,
+            samples: 120,
+            log_interval: 5.0,
+            csv: None,
+        }
+    }
+}
+
+/// Points in the frame a timestamp is written at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Marker {
+    MainBegin = 0,
+    MainEnd = 1,
+    /// Before the anti-aliasing pass, fxaa or the supersampling downsample.
+    FxaaBegin = 2,
+    FxaaEnd = 3,
+}
+
+const MARKERS: usize = 4;
+
+/// Gpu time spent in a single frame, in milliseconds.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTiming {
+    pub frame: u64,
+    pub main: f32,
+    pub fxaa: f32,
+}
+
+/// Rolling statistics over the last samples of a pass.
+#[derive(Clone, Debug, Default)]
+pub struct PassStats {
+    samples: VecDeque<f32>,
+}
+
+impl PassStats {
+    fn push(&mut self, value: f32, capacity: usize) {
+        while self.samples.len() >= capacity.max(1) {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn average(&self) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        self.samples.iter().sum::<f32>() / self.samples.len() as f32
+    }
+
+    /// Percentile in the 0-100 range using the nearest rank.
+    pub fn percentile(&self, percentile: f32) -> f32 {
+        if self.samples.is_empty() {
+            return 0.0;
+        }
+        let mut sorted = self.samples.iter().cloned().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let rank = (percentile / 100.0 * sorted.len() as f32).ceil() as usize;
+        sorted[rank.max(1).min(sorted.len()) - 1]
+    }
+}
+
+/// Measured gpu pass times, filled in by the timestamp nodes when profiling is enabled.
+#[derive(Debug, Default)]
+pub struct PassTimings {
+    /// `Some(false)` when the device can't write timestamps, `None` until the graph is built.
+    pub available: Option<bool>,
+    pub main: PassStats,
+    pub fxaa: PassStats,
+    /// Most recently resolved frame.
+    pub latest: Option<FrameTiming>,
+    // frames resolved since the profiling system last ran
+    resolved: Vec<FrameTiming>,
+    // timestamps in nanoseconds of frames that aren't complete yet
+    pending: BTreeMap<u64, [Option<f64>; MARKERS]>,
+    capacity: usize,
+}
+
+impl PassTimings {
+    fn record(&mut self, marker: Marker, frame: u64, nanoseconds: f64) {
+        let timestamps = self.pending.entry(frame).or_insert([None; MARKERS]);
+        timestamps[marker as usize] = Some(nanoseconds);
+        if let [Some(begin), Some(main_end), Some(fxaa_begin), Some(fxaa_end)] = *timestamps {
+            self.pending.remove(&frame);
+            let timing = FrameTiming {
+                frame,
+                main: ((main_end - begin) / 1_000_000.0) as f32,
+                fxaa: ((fxaa_end - fxaa_begin) / 1_000_000.0) as f32,
+            };
+            let capacity = self.capacity;
+            self.main.push(timing.main, capacity);
+            self.fxaa.push(timing.fxaa, capacity);
+            self.latest = Some(timing);
+            self.resolved.push(timing);
+        }
+
+        // drop frames that will never be completed, e.g. after the graph was rebuilt
+        while self.pending.len() > 16 {
+            let oldest = *self.pending.keys().next().unwrap();
+            self.pending.remove(&oldest);
+        }
+    }
+}
+
+type TimestampBuffer<B> = CommandBuffer<
+    B,
+    QueueType,
+    PendingState<ExecutableState<MultiShot<SimultaneousUse>>>,
+    PrimaryLevel,
+    IndividualReset,
+>;
+
+#[derive(Debug)]
+struct FrameQuery<B: hal::Backend> {
+    submit: Submit<B, SimultaneousUse>,
+    command_buffer: TimestampBuffer<B>,
+    pending: Option<u64>,
+}
+
+/// Node writing a single timestamp, ordered by the dependencies it is added with.
+#[derive(Debug)]
+pub struct TimestampDesc {
+    marker: Marker,
+}
+
+impl TimestampDesc {
+    pub fn new(marker: Marker) -> Self {
+        TimestampDesc { marker }
+    }
+}
+
+#[derive(Debug)]
+pub struct Timestamp<B: hal::Backend> {
+    marker: Marker,
+    // `None` when the device doesn't support timestamps
+    queries: Option<(B::QueryPool, CommandPool<B, QueueType, IndividualReset>)>,
+    frames: Vec<FrameQuery<B>>,
+    period: f32,
+}
+
+impl<B> NodeDesc<B, World> for TimestampDesc
+where
+    B: hal::Backend,
+{
+    type Node = Timestamp<B>;
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        world: &World,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Timestamp<B>, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.is_empty());
+
+        let limits = hal::adapter::PhysicalDevice::limits(factory.physical());
+        let available = limits.timestamp_compute_and_graphics && limits.timestamp_period > 0.0;
+        if let Some(mut timings) = world.try_fetch_mut::<PassTimings>() {
+            if timings.available.is_none() && !available {
+                log::warn!("GPU timestamps are unavailable on this device, pass timings disabled");
+            }
+            timings.available = Some(available);
+        }
+        if !available {
+            return Ok(Timestamp {
+                marker: self.marker,
+                queries: None,
+                frames: Vec::new(),
+                period: 0.0,
+            });
+        }
+
+        let frames_in_flight = ctx.frames_in_flight;
+        let query_pool = unsafe {
+            factory
+                .device()
+                .create_query_pool(hal::query::Type::Timestamp, frames_in_flight)?
+        };
+        let mut pool = factory
+            .create_command_pool(family)?
+            .with_individual_reset();
+
+        let mut frames = Vec::with_capacity(frames_in_flight as usize);
+        for index in 0..frames_in_flight {
+            unsafe {
+                let command_buffer = pool.allocate_buffers(1).remove(0);
+                let mut command_buffer = command_buffer.begin(MultiShot(SimultaneousUse), ());
+                let raw = command_buffer.raw();
+                raw.reset_query_pool(&query_pool, index..index + 1);
+                raw.write_timestamp(
+                    match self.marker {
+                        Marker::MainBegin => hal::pso::PipelineStage::TOP_OF_PIPE,
+                        _ => hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+                    },
+                    hal::query::Query {
+                        pool: &query_pool,
+                        id: index,
+                    },
+                );
+                let (submit, command_buffer) = command_buffer.finish().submit();
+                frames.push(FrameQuery {
+                    submit,
+                    command_buffer,
+                    pending: None,
+                });
+            }
+        }
+
+        Ok(Timestamp {
+            marker: self.marker,
+            queries: Some((query_pool, pool)),
+            frames,
+            period: limits.timestamp_period,
+        })
+    }
+}
+
+impl<B> Node<B, World> for Timestamp<B>
+where
+    B: hal::Backend,
+{
+    type Capability = QueueType;
+    type Desc = TimestampDesc;
+
+    unsafe fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        world: &World,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        let query_pool = match self.queries {
+            Some((ref query_pool, _)) => query_pool,
+            None => {
+                queue.submit(
+                    Some(
+                        Submission::new()
+                            .wait(waits.iter().cloned())
+                            .signal(signals.iter()),
+                    ),
+                    fence,
+                );
+                return;
+            }
+        };
+
+        // read back the timestamps of finished frames
+        let complete = frames.complete_upper_bound();
+        let mut timings = world.try_fetch_mut::<PassTimings>();
+        for (index, frame) in self.frames.iter_mut().enumerate() {
+            let finished = match frame.pending {
+                Some(pending) if pending < complete => pending,
+                _ => continue,
+            };
+            frame.pending = None;
+
+            let mut data = [0u8; 8];
+            let result = factory.device().get_query_pool_results(
+                query_pool,
+                index as u32..index as u32 + 1,
+                &mut data,
+                8,
+                hal::query::ResultFlags::BITS_64 | hal::query::ResultFlags::WAIT,
+            );
+            match (result, timings.as_mut()) {
+                (Ok(true), Some(timings)) => {
+                    let ticks = u64::from_ne_bytes(data);
+                    timings.record(self.marker, finished, ticks as f64 * self.period as f64);
+                }
+                (Err(err), _) => log::warn!("Failed to read GPU timestamp: {:?}", err),
+                _ => (),
+            }
+        }
+        drop(timings);
+
+        let index = frames.next().index();
+        let slot = (index % self.frames.len() as u64) as usize;
+        self.frames[slot].pending = Some(index);
+
+        queue.submit(
+            Some(
+                Submission::new()
+                    .submits(Some(&self.frames[slot].submit))
+                    .wait(waits.iter().cloned())
+                    .signal(signals.iter()),
+            ),
+            fence,
+        );
+    }
+
+    unsafe fn dispose(self, factory: &mut Factory<B>, _world: &World) {
+        if let Some((query_pool, mut pool)) = self.queries {
+            pool.free_buffers(
+                self.frames
+                    .into_iter()
+                    .map(|frame| frame.command_buffer.mark_complete()),
+            );
+            factory.destroy_command_pool(pool);
+            factory.device().destroy_query_pool(query_pool);
+        }
+    }
+}
+
+// system logging the pass timings and writing them to csv
+#[derive(Default)]
+pub struct ProfilingSystem {
+    since_log: f32,
+    csv: Option<BufWriter<File>>,
+}
+
+impl<'s> System<'s> for ProfilingSystem {
+    type SystemData = (
+        Read<'s, Time>,
+        Read<'s, ProfilingConfig>,
+        Write<'s, PassTimings>,
+    );
+
+    fn run(&mut self, (time, config, mut timings): Self::SystemData) {
+        if !config.enabled {
+            return;
+        }
+        timings.capacity = config.samples;
+
+        if self.csv.is_none() {
+            if let Some(ref path) = config.csv {
+                match File::create(path) {
+                    Ok(file) => {
+                        let mut csv = BufWriter::new(file);
+                        let _ = writeln!(csv, "frame,main_ms,fxaa_ms");
+                        self.csv = Some(csv);
+                    }
+                    Err(err) => log::error!("Failed to create {:?}: {}", path, err),
+                }
+            }
+        }
+        let resolved = std::mem::replace(&mut timings.resolved, Vec::new());
+        if let Some(ref mut csv) = self.csv {
+            for timing in &resolved {
+                let _ = writeln!(csv, "{},{:.4},{:.4}", timing.frame, timing.main, timing.fxaa);
+            }
+        }
+
+        if config.log_interval <= 0.0 {
+            return;
+        }
+        self.since_log += time.delta_seconds();
+        if self.since_log < config.log_interval {
+            return;
+        }
+        self.since_log = 0.0;
+
+        match timings.available {
+            Some(true) if !timings.main.is_empty() => log::info!(
+                "GPU main pass {:.3}ms (p50 {:.3}, p95 {:.3}), fxaa pass {:.3}ms (p50 {:.3}, p95 {:.3})",
+                timings.main.average(),
+                timings.main.percentile(50.0),
+                timings.main.percentile(95.0),
+                timings.fxaa.average(),
+                timings.fxaa.percentile(50.0),
+                timings.fxaa.percentile(95.0),
+            ),
+            Some(false) => log::info!("GPU pass timings unavailable"),
+            _ => (),
+        }
+    }
+}
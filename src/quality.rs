@@ -0,0 +1,710 @@
+// image quality metrics
+// compares the aliased and the anti-aliased version of a frame against a supersampled reference with
+// psnr, ssim and flip, writing per pixel heatmaps and the aggregates as json
+
+use crate::{
+    fxaa::{FxaaQuality, LumaMode},
+    fxaa_cpu,
+};
+use amethyst::{log, Error};
+use serde::Serialize;
+use std::f32::consts::PI;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+/// Options of the `quality` command.
+#[derive(Clone, Debug)]
+pub struct QualityOptions {
+    /// Reference frame, rendered at an integer multiple of the other images' size.
+    pub reference: PathBuf,
+    pub aliased: PathBuf,
+    /// Anti-aliased frame, computed from the aliased one with the cpu fxaa when missing.
+    pub fxaa: Option<PathBuf>,
+    /// Preset of the cpu fxaa.
+    pub quality: FxaaQuality,
+    /// Directory the heatmaps and `metrics.json` are written to.
+    pub output: PathBuf,
+    /// Pixels per degree of visual angle assumed by flip.
+    pub pixels_per_degree: f32,
+}
+
+impl Default for QualityOptions {
+    fn default() -> Self {
+        QualityOptions {
+            reference: PathBuf::new(),
+            aliased: PathBuf::new(),
+            fxaa: None,
+            quality: FxaaQuality::default(),
+            output: PathBuf::from("quality"),
+            // a 0.7m wide 4k monitor seen from 0.7m
+            pixels_per_degree: 67.0,
+        }
+    }
+}
+
+/// Rgba image with channels in the 0-1 range, rows from top to bottom.
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width,
+            height,
+            pixels: vec![[0.0; 4]; (width * height) as usize],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let image = image::open(path)
+            .map_err(|err| Error::from_string(format!("Failed to load {:?}: {}", path, err)))?
+            .to_rgba();
+        let (width, height) = image.dimensions();
+        let pixels = image
+            .pixels()
+            .map(|p| {
+                [
+                    p[0] as f32 / 255.0,
+                    p[1] as f32 / 255.0,
+                    p[2] as f32 / 255.0,
+                    p[3] as f32 / 255.0,
+                ]
+            })
+            .collect();
+        Ok(Image {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let bytes = self
+            .pixels
+            .iter()
+            .flat_map(|p| p.iter().map(|c| (c.max(0.0).min(1.0) * 255.0).round() as u8))
+            .collect::<Vec<_>>();
+        image::save_buffer(path, &bytes, self.width, self.height, image::ColorType::RGBA(8))
+            .map_err(|err| Error::from_string(format!("Failed to write {:?}: {}", path, err)))
+    }
+
+    /// Pixel with the coordinates clamped to the edges.
+    pub fn get(&self, x: i64, y: i64) -> [f32; 4] {
+        let x = x.max(0).min(self.width as i64 - 1) as u32;
+        let y = y.max(0).min(self.height as i64 - 1) as u32;
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: [f32; 4]) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    /// Bilinear sample at a position in pixels, pixel centers are at .5.
+    pub fn sample(&self, x: f32, y: f32) -> [f32; 4] {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let mut result = [0.0; 4];
+        for (dx, dy, weight) in &[
+            (0, 0, (1.0 - fx) * (1.0 - fy)),
+            (1, 0, fx * (1.0 - fy)),
+            (0, 1, (1.0 - fx) * fy),
+            (1, 1, fx * fy),
+        ] {
+            let p = self.get(x0 + dx, y0 + dy);
+            for c in 0..4 {
+                result[c] += p[c] * weight;
+            }
+        }
+        result
+    }
+
+    /// Average blocks of `factor` by `factor` pixels in linear light, like the supersampling
+    /// resolve of an sRGB image.
+    pub fn downsample(&self, factor: u32) -> Image {
+        let mut output = Image::new(self.width / factor, self.height / factor);
+        let weight = 1.0 / (factor * factor) as f32;
+        for y in 0..output.height {
+            for x in 0..output.width {
+                let mut sum = [0.0; 4];
+                for sy in 0..factor {
+                    for sx in 0..factor {
+                        let p = self.get((x * factor + sx) as i64, (y * factor + sy) as i64);
+                        let linear = srgb_to_linear(p);
+                        for c in 0..3 {
+                            sum[c] += linear[c] * weight;
+                        }
+                        sum[3] += p[3] * weight;
+                    }
+                }
+                let srgb = linear_to_srgb([sum[0], sum[1], sum[2]]);
+                output.set(x, y, [srgb[0], srgb[1], srgb[2], sum[3]]);
+            }
+        }
+        output
+    }
+}
+
+/// Aggregate metrics of an image against the reference.
+#[derive(Clone, Debug, Serialize)]
+pub struct Metrics {
+    /// Peak signal to noise ratio in dB, capped at 100 for identical images.
+    pub psnr: f32,
+    /// Mean structural similarity of the luma, 1 for identical images.
+    pub ssim: f32,
+    /// Mean flip error, 0 for identical images.
+    pub flip: f32,
+    pub flip_p95: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Report {
+    reference: PathBuf,
+    width: u32,
+    height: u32,
+    supersampling: u32,
+    cpu_fxaa: Option<FxaaQuality>,
+    aliased: Metrics,
+    fxaa: Metrics,
+}
+
+/// Run the `quality` command.
+pub fn run(options: &QualityOptions) -> Result<(), Error> {
+    let aliased = Image::load(&options.aliased)?;
+    let reference = Image::load(&options.reference)?;
+    let factor = reference.width / aliased.width.max(1);
+    if factor == 0
+        || reference.width != aliased.width * factor
+        || reference.height != aliased.height * factor
+    {
+        return Err(Error::from_string(format!(
+            "Reference is {}x{}, expected an integer multiple of {}x{}",
+            reference.width, reference.height, aliased.width, aliased.height
+        )));
+    }
+    let reference = reference.downsample(factor);
+
+    fs::create_dir_all(&options.output)
+        .map_err(|err| Error::from_string(format!("Failed to create {:?}: {}", options.output, err)))?;
+    let antialiased = match options.fxaa {
+        Some(ref path) => Image::load(path)?,
+        None => {
+            let image = fxaa_cpu::fxaa(&aliased, options.quality.params(), LumaMode::Perceptual);
+            image.save(&options.output.join("fxaa-cpu.png"))?;
+            image
+        }
+    };
+    if (antialiased.width, antialiased.height) != (aliased.width, aliased.height) {
+        return Err(Error::from_string("Aliased and anti-aliased images differ in size"));
+    }
+
+    let report = Report {
+        reference: options.reference.clone(),
+        width: aliased.width,
+        height: aliased.height,
+        supersampling: factor,
+        cpu_fxaa: options.fxaa.as_ref().map_or(Some(options.quality), |_| None),
+        aliased: evaluate(&reference, &aliased, options, "aliased")?,
+        fxaa: evaluate(&reference, &antialiased, options, "fxaa")?,
+    };
+    for (name, metrics) in &[("aliased", &report.aliased), ("fxaa", &report.fxaa)] {
+        log::info!(
+            "{}: PSNR {:.2}dB, SSIM {:.4}, FLIP {:.4} (p95 {:.4})",
+            name,
+            metrics.psnr,
+            metrics.ssim,
+            metrics.flip,
+            metrics.flip_p95
+        );
+    }
+
+    let path = options.output.join("metrics.json");
+    let file = File::create(&path)
+        .map_err(|err| Error::from_string(format!("Failed to create {:?}: {}", path, err)))?;
+    serde_json::to_writer_pretty(file, &report)
+        .map_err(|err| Error::from_string(format!("Failed to write {:?}: {}", path, err)))?;
+    log::info!("Quality report written to {:?}", options.output);
+    Ok(())
+}
+
+// compute the metrics of an image and write its heatmaps
+fn evaluate(
+    reference: &Image,
+    test: &Image,
+    options: &QualityOptions,
+    name: &str,
+) -> Result<Metrics, Error> {
+    let (width, height) = (test.width, test.height);
+
+    let squared = squared_error(reference, test);
+    let psnr = psnr(&squared);
+    let absolute = squared.iter().map(|e| e.sqrt()).collect::<Vec<_>>();
+    heatmap(width, height, &absolute).save(&options.output.join(format!("{}-error.png", name)))?;
+
+    let ssim = ssim_map(reference, test);
+    let dissimilarity = ssim.iter().map(|s| 1.0 - s).collect::<Vec<_>>();
+    heatmap(width, height, &dissimilarity).save(&options.output.join(format!("{}-ssim.png", name)))?;
+
+    let flip = flip_map(reference, test, options.pixels_per_degree);
+    heatmap(width, height, &flip).save(&options.output.join(format!("{}-flip.png", name)))?;
+    let mut sorted = flip.clone();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = (0.95 * sorted.len() as f32).ceil() as usize;
+
+    Ok(Metrics {
+        psnr,
+        ssim: ssim.iter().sum::<f32>() / ssim.len() as f32,
+        flip: flip.iter().sum::<f32>() / flip.len() as f32,
+        flip_p95: sorted[rank.max(1).min(sorted.len()) - 1],
+    })
+}
+
+// mean squared error of the rgb channels of each pixel
+fn squared_error(reference: &Image, test: &Image) -> Vec<f32> {
+    reference
+        .pixels
+        .iter()
+        .zip(&test.pixels)
+        .map(|(r, t)| (0..3).map(|c| (r[c] - t[c]).powi(2)).sum::<f32>() / 3.0)
+        .collect()
+}
+
+// peak signal to noise ratio of the per pixel squared errors, capped at 100dB
+fn psnr(squared: &[f32]) -> f32 {
+    let mse = squared.iter().sum::<f32>() / squared.len() as f32;
+    (10.0 * (1.0 / mse.max(1e-10)).log10()).min(100.0)
+}
+
+// color an error map in the 0-1 range, dark for no error and bright for large ones
+fn heatmap(width: u32, height: u32, values: &[f32]) -> Image {
+    const STOPS: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.016],
+        [0.314, 0.071, 0.486],
+        [0.718, 0.216, 0.475],
+        [0.988, 0.537, 0.380],
+        [0.988, 0.992, 0.749],
+    ];
+    let mut image = Image::new(width, height);
+    for (pixel, value) in image.pixels.iter_mut().zip(values) {
+        let position = value.max(0.0).min(1.0) * (STOPS.len() - 1) as f32;
+        let index = (position.floor() as usize).min(STOPS.len() - 2);
+        let t = position - index as f32;
+        let (a, b) = (STOPS[index], STOPS[index + 1]);
+        *pixel = [
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+            1.0,
+        ];
+    }
+    image
+}
+
+// convolve a single channel with a horizontal and a vertical kernel, clamping at the edges
+fn convolve(data: &[f32], width: u32, height: u32, kernel_x: &[f32], kernel_y: &[f32]) -> Vec<f32> {
+    let (width, height) = (width as i64, height as i64);
+    let at = |data: &[f32], x: i64, y: i64| {
+        data[(y.max(0).min(height - 1) * width + x.max(0).min(width - 1)) as usize]
+    };
+    let (radius_x, radius_y) = ((kernel_x.len() / 2) as i64, (kernel_y.len() / 2) as i64);
+    let mut horizontal = vec![0.0; data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            horizontal[(y * width + x) as usize] = kernel_x
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * at(data, x + i as i64 - radius_x, y))
+                .sum();
+        }
+    }
+    let mut output = vec![0.0; data.len()];
+    for y in 0..height {
+        for x in 0..width {
+            output[(y * width + x) as usize] = kernel_y
+                .iter()
+                .enumerate()
+                .map(|(i, k)| k * at(&horizontal, x, y + i as i64 - radius_y))
+                .sum();
+        }
+    }
+    output
+}
+
+fn gaussian_kernel(sigma: f32, radius: usize) -> Vec<f32> {
+    let kernel = (0..=2 * radius)
+        .map(|i| {
+            let x = i as f32 - radius as f32;
+            (-x * x / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<_>>();
+    let sum = kernel.iter().sum::<f32>();
+    kernel.into_iter().map(|k| k / sum).collect()
+}
+
+// structural similarity of the Rec.601 luma with an 11x11 gaussian window
+fn ssim_map(reference: &Image, test: &Image) -> Vec<f32> {
+    const C1: f32 = 0.01 * 0.01;
+    const C2: f32 = 0.03 * 0.03;
+    let luma = |image: &Image| {
+        image
+            .pixels
+            .iter()
+            .map(|p| p[0] * 0.299 + p[1] * 0.587 + p[2] * 0.114)
+            .collect::<Vec<_>>()
+    };
+    let (x, y) = (luma(reference), luma(test));
+    let (width, height) = (reference.width, reference.height);
+    let kernel = gaussian_kernel(1.5, 5);
+    let blur = |data: &[f32]| convolve(data, width, height, &kernel, &kernel);
+    let product = |a: &[f32], b: &[f32]| a.iter().zip(b).map(|(a, b)| a * b).collect::<Vec<_>>();
+
+    let (mean_x, mean_y) = (blur(&x), blur(&y));
+    let (mean_xx, mean_yy, mean_xy) = (
+        blur(&product(&x, &x)),
+        blur(&product(&y, &y)),
+        blur(&product(&x, &y)),
+    );
+    (0..x.len())
+        .map(|i| {
+            let (mx, my) = (mean_x[i], mean_y[i]);
+            let variance_x = mean_xx[i] - mx * mx;
+            let variance_y = mean_yy[i] - my * my;
+            let covariance = mean_xy[i] - mx * my;
+            ((2.0 * mx * my + C1) * (2.0 * covariance + C2))
+                / ((mx * mx + my * my + C1) * (variance_x + variance_y + C2))
+        })
+        .collect()
+}
+
+// ldr flip, following Andersson et al. 2020: the colors are filtered with contrast sensitivity
+// functions and compared in a hunt adjusted L*a*b* space, then amplified where edges or points differ
+fn flip_map(reference: &Image, test: &Image, pixels_per_degree: f32) -> Vec<f32> {
+    const QC: f32 = 0.7;
+    const QF: f32 = 0.5;
+    const PC: f32 = 0.4;
+    const PT: f32 = 0.95;
+    let (width, height) = (reference.width, reference.height);
+
+    let filtered_reference = csf_filter(reference, pixels_per_degree);
+    let filtered_test = csf_filter(test, pixels_per_degree);
+    let color_max = hyab(hunt(lab([0.0, 1.0, 0.0])), hunt(lab([0.0, 0.0, 1.0]))).powf(QC);
+
+    let luminance = |image: &Image| {
+        image
+            .pixels
+            .iter()
+            .map(|p| rgb_to_xyz(srgb_to_linear(*p))[1])
+            .collect::<Vec<_>>()
+    };
+    let (edges_reference, points_reference) =
+        features(&luminance(reference), width, height, pixels_per_degree);
+    let (edges_test, points_test) = features(&luminance(test), width, height, pixels_per_degree);
+
+    (0..filtered_reference.len())
+        .map(|i| {
+            let distance = hyab(hunt(lab(filtered_reference[i])), hunt(lab(filtered_test[i]))).powf(QC);
+            let color_error = if distance < PC * color_max {
+                PT / (PC * color_max) * distance
+            } else {
+                PT + (distance - PC * color_max) / (color_max - PC * color_max) * (1.0 - PT)
+            };
+            let edge_difference = (edges_reference[i] - edges_test[i]).abs();
+            let point_difference = (points_reference[i] - points_test[i]).abs();
+            let feature_error = (edge_difference.max(point_difference) / 2.0f32.sqrt()).powf(QF);
+            color_error.powf(1.0 - feature_error).min(1.0)
+        })
+        .collect()
+}
+
+// apply the contrast sensitivity functions in the opponent YCxCz space, returns linear rgb
+fn csf_filter(image: &Image, pixels_per_degree: f32) -> Vec<[f32; 3]> {
+    // (a1, b1, a2, b2) of the achromatic, red-green and blue-yellow channels
+    const PARAMETERS: [[f32; 4]; 3] = [
+        [1.0, 0.0047, 0.0, 1e-5],
+        [1.0, 0.0053, 0.0, 1e-5],
+        [34.1, 0.04, 13.5, 0.025],
+    ];
+    let radius = (3.0 * (0.04f32 / (2.0 * PI * PI)).sqrt() * pixels_per_degree).ceil() as usize;
+    let (width, height) = (image.width, image.height);
+
+    let ycxcz = image
+        .pixels
+        .iter()
+        .map(|p| xyz_to_ycxcz(rgb_to_xyz(srgb_to_linear(*p))))
+        .collect::<Vec<_>>();
+    let mut filtered = vec![[0.0; 3]; ycxcz.len()];
+    for (channel, &[a1, b1, a2, b2]) in PARAMETERS.iter().enumerate() {
+        let data = ycxcz.iter().map(|p| p[channel]).collect::<Vec<_>>();
+        // each term of the filter is a separable gaussian, weighted by its share of the 2d sum
+        let mut terms = Vec::new();
+        for &(a, b) in &[(a1, b1), (a2, b2)] {
+            if a == 0.0 {
+                continue;
+            }
+            let kernel = (0..=2 * radius)
+                .map(|i| {
+                    let x = (i as f32 - radius as f32) / pixels_per_degree;
+                    (-PI * PI * x * x / b).exp()
+                })
+                .collect::<Vec<_>>();
+            let sum = kernel.iter().sum::<f32>();
+            let weight = a * (PI / b).sqrt() * sum * sum;
+            let kernel = kernel.into_iter().map(|k| k / sum).collect::<Vec<_>>();
+            terms.push((weight, convolve(&data, width, height, &kernel, &kernel)));
+        }
+        let total = terms.iter().map(|(weight, _)| weight).sum::<f32>();
+        for (weight, values) in &terms {
+            for (pixel, value) in filtered.iter_mut().zip(values) {
+                pixel[channel] += weight / total * value;
+            }
+        }
+    }
+    filtered
+        .into_iter()
+        .map(|p| {
+            let rgb = xyz_to_rgb(ycxcz_to_xyz(p));
+            [
+                rgb[0].max(0.0).min(1.0),
+                rgb[1].max(0.0).min(1.0),
+                rgb[2].max(0.0).min(1.0),
+            ]
+        })
+        .collect()
+}
+
+// magnitudes of the edge and point features of a luminance image
+fn features(luminance: &[f32], width: u32, height: u32, pixels_per_degree: f32) -> (Vec<f32>, Vec<f32>) {
+    let sigma = 0.5 * 0.082 * pixels_per_degree;
+    let radius = (3.0 * sigma).ceil() as usize;
+    let gaussian = (0..=2 * radius)
+        .map(|i| {
+            let x = i as f32 - radius as f32;
+            (-x * x / (2.0 * sigma * sigma)).exp()
+        })
+        .collect::<Vec<_>>();
+    let gaussian_sum = gaussian.iter().sum::<f32>();
+    let smooth = gaussian.iter().map(|g| g / gaussian_sum).collect::<Vec<_>>();
+    // derivative kernels with their positive and negative weights each summing to one
+    let derivative = |shape: &dyn Fn(f32) -> f32| {
+        let kernel = gaussian
+            .iter()
+            .enumerate()
+            .map(|(i, g)| shape(i as f32 - radius as f32) * g)
+            .collect::<Vec<_>>();
+        let positive = kernel.iter().filter(|k| **k > 0.0).sum::<f32>();
+        let negative = -kernel.iter().filter(|k| **k < 0.0).sum::<f32>();
+        kernel
+            .into_iter()
+            .map(|k| if k < 0.0 { k / negative } else { k / positive })
+            .collect::<Vec<_>>()
+    };
+    let edge = derivative(&|x: f32| -x);
+    let point = derivative(&|x: f32| x * x / (sigma * sigma) - 1.0);
+
+    let magnitude = |kernel: &[f32]| {
+        let along_x = convolve(luminance, width, height, kernel, &smooth);
+        let along_y = convolve(luminance, width, height, &smooth, kernel);
+        along_x
+            .iter()
+            .zip(&along_y)
+            .map(|(x, y)| (x * x + y * y).sqrt())
+            .collect::<Vec<_>>()
+    };
+    (magnitude(&edge), magnitude(&point))
+}
+
+// d65 reference white
+const WHITE: [f32; 3] = [0.950_428_5, 1.0, 1.088_900_4];
+
+fn srgb_to_linear(p: [f32; 4]) -> [f32; 3] {
+    let channel = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    [channel(p[0]), channel(p[1]), channel(p[2])]
+}
+
+fn linear_to_srgb(c: [f32; 3]) -> [f32; 3] {
+    let channel = |c: f32| {
+        if c <= 0.003_130_8 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    [channel(c[0]), channel(c[1]), channel(c[2])]
+}
+
+fn rgb_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    [
+        0.412_456_4 * c[0] + 0.357_576_1 * c[1] + 0.180_437_5 * c[2],
+        0.212_672_9 * c[0] + 0.715_152_2 * c[1] + 0.072_175 * c[2],
+        0.019_333_9 * c[0] + 0.119_192 * c[1] + 0.950_304_1 * c[2],
+    ]
+}
+
+fn xyz_to_rgb(c: [f32; 3]) -> [f32; 3] {
+    [
+        3.240_454_2 * c[0] - 1.537_138_5 * c[1] - 0.498_531_4 * c[2],
+        -0.969_266 * c[0] + 1.876_010_8 * c[1] + 0.041_556 * c[2],
+        0.055_643_4 * c[0] - 0.204_025_9 * c[1] + 1.057_225_2 * c[2],
+    ]
+}
+
+fn xyz_to_ycxcz(c: [f32; 3]) -> [f32; 3] {
+    let (x, y, z) = (c[0] / WHITE[0], c[1] / WHITE[1], c[2] / WHITE[2]);
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+fn ycxcz_to_xyz(c: [f32; 3]) -> [f32; 3] {
+    let y = (c[0] + 16.0) / 116.0;
+    let x = c[1] / 500.0 + y;
+    let z = y - c[2] / 200.0;
+    [x * WHITE[0], y * WHITE[1], z * WHITE[2]]
+}
+
+// CIELAB of a linear rgb color
+fn lab(rgb: [f32; 3]) -> [f32; 3] {
+    let xyz = rgb_to_xyz(rgb);
+    let delta = 6.0f32 / 29.0;
+    let f = |t: f32| {
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let (x, y, z) = (f(xyz[0] / WHITE[0]), f(xyz[1] / WHITE[1]), f(xyz[2] / WHITE[2]));
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+// scale the chroma with lightness, dark colors are harder to tell apart
+fn hunt(lab: [f32; 3]) -> [f32; 3] {
+    [lab[0], 0.01 * lab[0] * lab[1], 0.01 * lab[0] * lab[2]]
+}
+
+fn hyab(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flat(width: u32, height: u32, color: [f32; 4]) -> Image {
+        let mut image = Image::new(width, height);
+        image.pixels.iter_mut().for_each(|p| *p = color);
+        image
+    }
+
+    // black and white diagonal stripes, with plenty of edges for every metric
+    fn stripes(width: u32, height: u32) -> Image {
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let value = if (x + y) / 3 % 2 == 0 { 1.0 } else { 0.0 };
+                image.set(x, y, [value, value, value, 1.0]);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn identical_images() {
+        let image = stripes(32, 24);
+        assert_eq!(psnr(&squared_error(&image, &image)), 100.0);
+        let ssim = ssim_map(&image, &image);
+        assert!(ssim.iter().all(|s| (s - 1.0).abs() < 1e-4), "{:?}", ssim);
+        let flip = flip_map(&image, &image, 67.0);
+        assert!(flip.iter().all(|f| f.abs() < 1e-6), "{:?}", flip);
+    }
+
+    #[test]
+    fn psnr_of_one_pixel() {
+        let reference = flat(10, 10, [0.0, 0.0, 0.0, 1.0]);
+        let mut test = reference.clone();
+        // a full scale error in one pixel out of 100 is a mse of 0.01, so 20dB
+        test.set(4, 7, [1.0, 1.0, 1.0, 1.0]);
+        assert!((psnr(&squared_error(&reference, &test)) - 20.0).abs() < 1e-4);
+        // half scale in one of the three channels is a mse of 0.25 / 3 / 100
+        test.set(4, 7, [0.0, 0.5, 0.0, 1.0]);
+        let expected = 10.0 * (1.0 / (0.25 / 3.0 / 100.0f32)).log10();
+        assert!((psnr(&squared_error(&reference, &test)) - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn different_images() {
+        let reference = stripes(32, 24);
+        let test = flat(32, 24, [0.5, 0.5, 0.5, 1.0]);
+        assert!(psnr(&squared_error(&reference, &test)) < 10.0);
+        let ssim = ssim_map(&reference, &test);
+        assert!(ssim.iter().sum::<f32>() / (ssim.len() as f32) < 0.5);
+        let flip = flip_map(&reference, &test, 67.0);
+        assert!(flip.iter().all(|f| *f >= 0.0 && *f <= 1.0));
+        assert!(flip.iter().sum::<f32>() / (flip.len() as f32) > 0.1);
+    }
+
+    #[test]
+    fn downsample_keeps_a_constant_image() {
+        let color = [0.2, 0.4, 0.6, 0.8];
+        for factor in 1..=4 {
+            let image = flat(24, 12, color).downsample(factor);
+            assert_eq!((image.width, image.height), (24 / factor, 12 / factor));
+            for p in &image.pixels {
+                for c in 0..4 {
+                    assert!((p[c] - color[c]).abs() < 1e-5, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn downsample_averages_blocks() {
+        let image = stripes(6, 6).downsample(6);
+        let mean = stripes(6, 6).pixels.iter().map(|p| p[0]).sum::<f32>() / 36.0;
+        // black and white are the same in both encodings, their mean is encoded back to sRGB
+        assert!((image.pixels[0][0] - linear_to_srgb([mean; 3])[0]).abs() < 1e-5);
+        assert!((image.pixels[0][3] - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn downsample_averages_in_linear_light() {
+        let mut image = flat(2, 2, [0.0, 0.0, 0.0, 1.0]);
+        image.set(1, 0, [1.0, 1.0, 1.0, 0.0]);
+        image.set(1, 1, [1.0, 1.0, 1.0, 0.0]);
+        let p = image.downsample(2).pixels[0];
+        // half of white in linear light, far brighter than the encoded 0.5
+        assert!((p[0] - 0.735_4).abs() < 1e-3, "{:?}", p);
+        assert!((p[3] - 0.5).abs() < 1e-5, "{:?}", p);
+    }
+
+    #[test]
+    fn cpu_fxaa_keeps_a_flat_image() {
+        let image = flat(16, 16, [0.3, 0.6, 0.9, 1.0]);
+        for quality in &FxaaQuality::ALL {
+            let output = fxaa_cpu::fxaa(&image, quality.params(), LumaMode::Perceptual);
+            for p in &output.pixels {
+                for c in 0..4 {
+                    assert!((p[c] - image.pixels[0][c]).abs() < 1e-5, "{:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cpu_fxaa_softens_edges() {
+        let image = stripes(32, 32);
+        let output = fxaa_cpu::fxaa(&image, FxaaQuality::High.params(), LumaMode::Perceptual);
+        assert!(output.pixels.iter().all(|p| p[0] >= 0.0 && p[0] <= 1.0));
+        assert!(output.pixels.iter().any(|p| p[0] > 0.01 && p[0] < 0.99));
+        assert!(psnr(&squared_error(&image, &output)) < 100.0);
+    }
+}
@@ -0,0 +1,444 @@
+// shader reflection
+// checks the std140 layout of the rust uniform structs, the descriptor sets and the vertex formats
+// against the spir-v, so a change on one side only fails the pipeline build instead of corrupting
+// rendering or crashing in the validation layers
+
+use rendy::{
+    graph::render::SetLayout,
+    hal::{self, format::Format, pso::DescriptorType},
+    mesh::VertexFormat,
+};
+use spirv_reflect::{
+    types::{ReflectDecorationFlags, ReflectDescriptorType, ReflectFormat},
+    ShaderModule,
+};
+
+/// Member of a std140 uniform struct, as laid out on the rust side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UniformMember {
+    pub name: &'static str,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Layout of a rust uniform struct after conversion to std140.
+#[derive(Clone, Debug)]
+pub struct UniformLayout {
+    pub name: &'static str,
+    pub size: u32,
+    pub members: Vec<UniformMember>,
+}
+
+/// Describe the std140 layout of an `AsStd140` struct from its field names, e.g.
+/// `uniform_layout!(DownsampleArgs { source_size, target_size, factor, filter_mode })`.
+#[macro_export]
+macro_rules! uniform_layout {
+    ($ty:ident { $($field:ident),* $(,)? }) => {{
+        type Std140 = <$ty as glsl_layout::AsStd140>::Std140;
+        // every std140 member is plain numbers, so all zeroes is a valid value
+        let value: Std140 = unsafe { std::mem::zeroed() };
+        let base = &value as *const Std140 as usize;
+        $crate::reflect::UniformLayout {
+            name: stringify!($ty),
+            size: std::mem::size_of::<Std140>() as u32,
+            members: vec![$(
+                $crate::reflect::UniformMember {
+                    name: stringify!($field),
+                    offset: (&value.$field as *const _ as usize - base) as u32,
+                    size: std::mem::size_of_val(&value.$field) as u32,
+                },
+            )*],
+        }
+    }};
+}
+
+/// Check that the uniform block at `set` and `binding` of the spir-v matches the rust layout member
+/// by member, in name, offset and size.
+pub fn check_uniform_block(
+    spirv: &[u8],
+    set: u32,
+    binding: u32,
+    layout: &UniformLayout,
+) -> Result<(), failure::Error> {
+    let module = ShaderModule::load_u8_data(spirv)
+        .map_err(|err| failure::format_err!("Failed to reflect shader: {}", err))?;
+    let bindings = module
+        .enumerate_descriptor_bindings(None)
+        .map_err(|err| failure::format_err!("Failed to reflect shader bindings: {}", err))?;
+    let block = bindings
+        .iter()
+        .find(|b| b.set == set && b.binding == binding)
+        .ok_or_else(|| {
+            failure::format_err!(
+                "{}: the shader has no binding {} in set {}",
+                layout.name,
+                binding,
+                set
+            )
+        })?;
+    if block.descriptor_type != ReflectDescriptorType::UniformBuffer {
+        failure::bail!(
+            "{}: binding {} in set {} is a {:?}, not a uniform buffer",
+            layout.name,
+            binding,
+            set,
+            block.descriptor_type
+        );
+    }
+
+    let mut errors = Vec::new();
+    for member in &block.block.members {
+        match layout.members.iter().find(|m| m.name == member.name) {
+            Some(rust) if rust.offset != member.offset || rust.size != member.size => {
+                errors.push(format!(
+                    "{} is at offset {} with size {} in the shader but at offset {} with size {} in rust",
+                    member.name, member.offset, member.size, rust.offset, rust.size
+                ))
+            }
+            Some(_) => (),
+            None => errors.push(format!("{} is missing in rust", member.name)),
+        }
+    }
+    for rust in &layout.members {
+        if !block.block.members.iter().any(|m| m.name == rust.name) {
+            errors.push(format!("{} is missing in the shader", rust.name));
+        }
+    }
+    // the rust struct may only be padded up to the std140 struct alignment
+    let padded = (block.block.size + 15) / 16 * 16;
+    if layout.size < block.block.size || layout.size > padded {
+        errors.push(format!(
+            "the block is {} bytes in the shader but {} bytes in rust",
+            block.block.size, layout.size
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(failure::format_err!(
+            "{} doesn't match the uniform block of the shader: {}",
+            layout.name,
+            errors.join(", ")
+        ))
+    }
+}
+
+/// Descriptor set of a pipeline, the binding number of each descriptor being its position in the
+/// list. The set layout, the descriptor pool and the set writes are all derived from it, so adding
+/// a binding only means adding it here, in the shader and in the descriptors written.
+#[derive(Clone, Debug)]
+pub struct SetDescription {
+    bindings: Vec<DescriptorType>,
+}
+
+impl SetDescription {
+    pub fn new(bindings: Vec<DescriptorType>) -> Self {
+        SetDescription { bindings }
+    }
+
+    /// Layout of the set, visible to the fragment shader.
+    pub fn layout(&self) -> SetLayout {
+        SetLayout {
+            bindings: self
+                .bindings
+                .iter()
+                .enumerate()
+                .map(|(binding, ty)| hal::pso::DescriptorSetLayoutBinding {
+                    binding: binding as u32,
+                    ty: *ty,
+                    count: 1,
+                    stage_flags: hal::pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        }
+    }
+
+    /// Pool sizes for allocating `sets` of these sets.
+    pub fn pool_ranges(&self, sets: usize) -> Vec<hal::pso::DescriptorRangeDesc> {
+        let mut ranges: Vec<hal::pso::DescriptorRangeDesc> = Vec::new();
+        for ty in &self.bindings {
+            match ranges.iter_mut().find(|range| range.ty == *ty) {
+                Some(range) => range.count += sets,
+                None => ranges.push(hal::pso::DescriptorRangeDesc { ty: *ty, count: sets }),
+            }
+        }
+        ranges
+    }
+
+    /// Writes of the descriptors to `set`, in binding order. Fails if they don't match the
+    /// description in number or kind.
+    pub fn writes<'a, B: hal::Backend>(
+        &self,
+        set: &'a B::DescriptorSet,
+        descriptors: Vec<hal::pso::Descriptor<'a, B>>,
+    ) -> Result<Vec<hal::pso::DescriptorSetWrite<'a, B, Option<hal::pso::Descriptor<'a, B>>>>, failure::Error>
+    {
+        if descriptors.len() != self.bindings.len() {
+            failure::bail!(
+                "{} descriptors written to a set of {} bindings",
+                descriptors.len(),
+                self.bindings.len()
+            );
+        }
+        descriptors
+            .into_iter()
+            .zip(&self.bindings)
+            .enumerate()
+            .map(|(binding, (descriptor, ty))| {
+                let matches = match (&descriptor, ty) {
+                    (hal::pso::Descriptor::Buffer(..), DescriptorType::UniformBuffer)
+                    | (hal::pso::Descriptor::Buffer(..), DescriptorType::StorageBuffer)
+                    | (hal::pso::Descriptor::CombinedImageSampler(..), DescriptorType::CombinedImageSampler)
+                    | (hal::pso::Descriptor::Image(..), DescriptorType::SampledImage)
+                    | (hal::pso::Descriptor::Image(..), DescriptorType::StorageImage)
+                    | (hal::pso::Descriptor::Sampler(..), DescriptorType::Sampler) => true,
+                    _ => false,
+                };
+                if !matches {
+                    failure::bail!("Binding {} is a {:?} but was written another kind of descriptor", binding, ty);
+                }
+                Ok(hal::pso::DescriptorSetWrite {
+                    set,
+                    binding: binding as u32,
+                    array_offset: 0,
+                    descriptors: Some(descriptor),
+                })
+            })
+            .collect()
+    }
+
+    /// Check the description against the bindings the shader declares in `set`.
+    pub fn check_shader(&self, spirv: &[u8], set: u32) -> Result<(), failure::Error> {
+        let module = ShaderModule::load_u8_data(spirv)
+            .map_err(|err| failure::format_err!("Failed to reflect shader: {}", err))?;
+        let bindings = module
+            .enumerate_descriptor_bindings(None)
+            .map_err(|err| failure::format_err!("Failed to reflect shader bindings: {}", err))?;
+
+        let mut errors = Vec::new();
+        for reflected in bindings.iter().filter(|b| b.set == set) {
+            match self.bindings.get(reflected.binding as usize) {
+                Some(ty) if reflect_type(*ty) != Some(reflected.descriptor_type) => errors.push(format!(
+                    "binding {} ({}) is a {:?} in the shader but a {:?} in rust",
+                    reflected.binding, reflected.name, reflected.descriptor_type, ty
+                )),
+                Some(_) => (),
+                None => errors.push(format!(
+                    "binding {} ({}) of the shader is missing in rust",
+                    reflected.binding, reflected.name
+                )),
+            }
+        }
+        for binding in 0..self.bindings.len() as u32 {
+            if !bindings.iter().any(|b| b.set == set && b.binding == binding) {
+                errors.push(format!("binding {} is missing in the shader", binding));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(failure::format_err!(
+                "The descriptor set doesn't match set {} of the shader: {}",
+                set,
+                errors.join(", ")
+            ))
+        }
+    }
+}
+
+// reflected type of a descriptor type, for the types the pipelines use
+fn reflect_type(ty: DescriptorType) -> Option<ReflectDescriptorType> {
+    match ty {
+        DescriptorType::Sampler => Some(ReflectDescriptorType::Sampler),
+        DescriptorType::CombinedImageSampler => Some(ReflectDescriptorType::CombinedImageSampler),
+        DescriptorType::SampledImage => Some(ReflectDescriptorType::SampledImage),
+        DescriptorType::StorageImage => Some(ReflectDescriptorType::StorageImage),
+        DescriptorType::UniformBuffer => Some(ReflectDescriptorType::UniformBuffer),
+        DescriptorType::StorageBuffer => Some(ReflectDescriptorType::StorageBuffer),
+        _ => None,
+    }
+}
+
+/// Check that the vertex format provides the inputs of the vertex shader, location by location.
+pub fn check_vertex_input(spirv: &[u8], format: &VertexFormat) -> Result<(), failure::Error> {
+    let module = ShaderModule::load_u8_data(spirv)
+        .map_err(|err| failure::format_err!("Failed to reflect shader: {}", err))?;
+    let inputs = module
+        .enumerate_input_variables(None)
+        .map_err(|err| failure::format_err!("Failed to reflect shader inputs: {}", err))?;
+
+    let (elements, _, _) = format.gfx_vertex_input_desc(hal::pso::VertexInputRate::Vertex);
+    let mut errors = Vec::new();
+    for input in inputs
+        .iter()
+        .filter(|input| !input.decoration_flags.contains(ReflectDecorationFlags::BUILT_IN))
+    {
+        let attribute = elements.get(input.location as usize).map(|element| element.format);
+        match attribute {
+            Some(attribute) if reflect_format(attribute) != Some(input.format) => errors.push(format!(
+                "location {} ({}) is a {:?} in the shader but a {:?} in rust",
+                input.location, input.name, input.format, attribute
+            )),
+            Some(_) => (),
+            None => errors.push(format!(
+                "location {} ({}) of the shader has no vertex attribute",
+                input.location, input.name
+            )),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(failure::format_err!(
+            "The vertex format doesn't match the vertex shader: {}",
+            errors.join(", ")
+        ))
+    }
+}
+
+// reflected format of a vertex attribute format, for the float formats the pipelines use
+fn reflect_format(format: Format) -> Option<ReflectFormat> {
+    match format {
+        Format::R32Sfloat => Some(ReflectFormat::R32_SFLOAT),
+        Format::Rg32Sfloat => Some(ReflectFormat::R32G32_SFLOAT),
+        Format::Rgb32Sfloat => Some(ReflectFormat::R32G32B32_SFLOAT),
+        Format::Rgba32Sfloat => Some(ReflectFormat::R32G32B32A32_SFLOAT),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssaa::DownsampleArgs;
+    use amethyst::renderer::pod::ViewArgs;
+    use rendy::mesh::{AsVertex, Position};
+
+    const DOWNSAMPLE_SPIRV: &[u8] = include_bytes!("../assets/shader/downsample.frag.spv");
+    const FXAA_SPIRV: &[u8] = include_bytes!("../assets/shader/fxaa.frag.spv");
+    const FXAA_STATS_SPIRV: &[u8] = include_bytes!("../assets/shader/fxaa_stats.frag.spv");
+    const MASK_VERTEX_SPIRV: &[u8] = include_bytes!("../assets/shader/mask.vert.spv");
+    const MASK_FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/mask.frag.spv");
+
+    fn downsample_layout() -> UniformLayout {
+        crate::uniform_layout!(DownsampleArgs {
+            source_size,
+            target_size,
+            factor,
+            filter_mode,
+        })
+    }
+
+    // every full-screen pass checks its uniform struct, descriptor set and vertex input against
+    // the embedded spir-v, the copy pass is checked in postprocess
+    #[test]
+    fn passes_match_their_shaders() {
+        let passes = vec![
+            crate::fxaa::pass((64, 64), false).validate(),
+            crate::fxaa::pass((64, 64), true).validate(),
+            crate::ssaa::pass((64, 64), 2).validate(),
+            crate::sharpen::pass((64, 64)).validate(),
+            crate::color_grade::pass((64, 64)).validate(),
+            crate::dither::pass((64, 64)).validate(),
+            crate::upscale::easu_pass((64, 64)).validate(),
+            crate::upscale::rcas_pass((64, 64)).validate(),
+        ];
+        for result in passes {
+            if let Err(err) = result {
+                panic!("{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn std140_offsets_match_the_shader() {
+        let layout = crate::fxaa::uniform_layout();
+        // bools and ints take 4 bytes, the vec2 of the depth planes is aligned to 8
+        let offsets = layout.members.iter().map(|m| (m.name, m.offset, m.size)).collect::<Vec<_>>();
+        assert_eq!(
+            offsets,
+            vec![
+                ("source_size", 0, 8),
+                ("target_size", 8, 8),
+                ("enabled", 16, 4),
+                ("luma_mode", 20, 4),
+                ("depth_edges", 24, 4),
+                ("depth_threshold", 28, 4),
+                ("depth_planes", 32, 8),
+                ("alpha_mode", 40, 4),
+                ("edge_threshold_min", 44, 4),
+                ("edge_threshold_max", 48, 4),
+                ("subpixel_quality", 52, 4),
+                ("iterations", 56, 4),
+                ("debug_view", 60, 4),
+            ]
+        );
+        // both variants share the block, the stats one only adds the storage buffer
+        check_uniform_block(FXAA_SPIRV, 0, 0, &layout).unwrap();
+        check_uniform_block(FXAA_STATS_SPIRV, 0, 0, &layout).unwrap();
+    }
+
+    #[test]
+    fn mask_matches_its_shaders() {
+        check_uniform_block(MASK_VERTEX_SPIRV, 0, 0, &crate::uniform_layout!(ViewArgs { proj, view, proj_view })).unwrap();
+        check_vertex_input(MASK_VERTEX_SPIRV, &Position::vertex()).unwrap();
+        SetDescription::new(vec![DescriptorType::UniformBuffer])
+            .check_shader(MASK_VERTEX_SPIRV, 0)
+            .unwrap();
+        SetDescription::new(vec![]).check_shader(MASK_FRAGMENT_SPIRV, 0).unwrap();
+    }
+
+    #[test]
+    fn moved_member_is_rejected() {
+        let mut layout = downsample_layout();
+        layout.members[2].offset += 4;
+        let err = check_uniform_block(DOWNSAMPLE_SPIRV, 0, 0, &layout).unwrap_err();
+        assert!(err.to_string().contains("factor is at offset 16"), "{}", err);
+    }
+
+    #[test]
+    fn missing_members_are_rejected() {
+        let mut layout = downsample_layout();
+        layout.members.pop();
+        let err = check_uniform_block(DOWNSAMPLE_SPIRV, 0, 0, &layout).unwrap_err();
+        assert!(err.to_string().contains("filter_mode is missing in rust"), "{}", err);
+
+        let mut layout = downsample_layout();
+        layout.members.push(UniformMember {
+            name: "sharpness",
+            offset: 24,
+            size: 4,
+        });
+        layout.size = 32;
+        let err = check_uniform_block(DOWNSAMPLE_SPIRV, 0, 0, &layout).unwrap_err();
+        assert!(err.to_string().contains("sharpness is missing in the shader"), "{}", err);
+    }
+
+    #[test]
+    fn wrong_binding_is_rejected() {
+        // binding 1 of the downsample shader is the sampled image
+        assert!(check_uniform_block(DOWNSAMPLE_SPIRV, 0, 1, &downsample_layout()).is_err());
+        assert!(check_uniform_block(DOWNSAMPLE_SPIRV, 1, 0, &downsample_layout()).is_err());
+    }
+
+    #[test]
+    fn wrong_descriptor_set_is_rejected() {
+        let set = SetDescription::new(vec![DescriptorType::UniformBuffer, DescriptorType::StorageBuffer]);
+        let err = set.check_shader(DOWNSAMPLE_SPIRV, 0).unwrap_err();
+        assert!(err.to_string().contains("binding 1"), "{}", err);
+        let set = SetDescription::new(vec![DescriptorType::UniformBuffer]);
+        assert!(set.check_shader(DOWNSAMPLE_SPIRV, 0).is_err());
+    }
+
+    #[test]
+    fn wrong_vertex_format_is_rejected() {
+        // a vec2 position where the mask shader reads a vec3
+        let format = crate::fullscreen::FullscreenVertexArgs::vertex();
+        let err = check_vertex_input(MASK_VERTEX_SPIRV, &format).unwrap_err();
+        assert!(err.to_string().contains("location 0 (position)"), "{}", err);
+    }
+}
@@ -0,0 +1,208 @@
+// render scale / dynamic resolution
+// the main pass renders at a scaled resolution and the fxaa pass samples that image while writing
+// the full resolution target, or an upscaler follows fxaa at the scaled resolution, the dynamic mode
+// adjusts the scale to stay around a frame time target
+
+use amethyst::{
+    core::Time,
+    ecs::prelude::{Read, System, SystemData, Write},
+    log,
+};
+use crate::upscale::{UpscaleQuality, Upscaler};
+use serde::{Deserialize, Serialize};
+
+/// Render scale configuration, loaded from `config/render_scale.ron`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RenderScaleConfig {
+    /// Initial scale of the main pass relative to the window.
+    pub scale: f32,
+    pub min_scale: f32,
+    pub max_scale: f32,
+    /// Adjust the scale at runtime to hit `target_frame_time`.
+    pub dynamic: bool,
+    /// Frame time target in milliseconds.
+    pub target_frame_time: f32,
+    pub upscaler: Upscaler,
+    /// Upscaling preset overriding `scale`.
+    pub quality: Option<UpscaleQuality>,
+    /// Sharpening after the `Fsr` upscaler in stops, 0.0 is the strongest.
+    pub sharpness: f32,
+}
+
+impl Default for RenderScaleConfig {
+    fn default() -> Self {
+        RenderScaleConfig {
+            scale: 1.0,
+            min_scale: 0.5,
+            max_scale: 2.0,
+            dynamic: false,
+            target_frame_time: 16.6,
+            upscaler: Upscaler::default(),
+            quality: None,
+            sharpness: 0.2,
+        }
+    }
+}
+
+impl RenderScaleConfig {
+    /// Scale the main pass starts at, from the upscaling preset if there is one. A preset's scale
+    /// is kept exact, on the 0.05 steps 1/1.5 would render 0.65 of the window instead of 2/3.
+    pub fn initial_scale(&self) -> RenderScale {
+        match self.quality {
+            Some(quality) => RenderScale::exact(quality.scale()),
+            None => RenderScale::new(self.scale),
+        }
+    }
+}
+
+/// Current scale of the main pass, the graph is rebuilt when it changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderScale {
+    pub scale: f32,
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        RenderScale { scale: 1.0 }
+    }
+}
+
+impl RenderScale {
+    const STEP: f32 = 0.05;
+
+    pub fn new(scale: f32) -> Self {
+        RenderScale {
+            scale: Self::quantize(scale),
+        }
+    }
+
+    /// Scale that isn't moved to the fixed steps, for the upscaling presets.
+    pub fn exact(scale: f32) -> Self {
+        RenderScale { scale }
+    }
+
+    // keep the scale on fixed steps so small changes don't rebuild the graph
+    fn quantize(scale: f32) -> f32 {
+        (scale / Self::STEP).round() * Self::STEP
+    }
+
+    /// Size of the main pass targets for a window of the given size, never smaller than a pixel.
+    pub fn apply(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = |size: u32| ((size as f32 * self.scale).round() as u32).max(1);
+        (scale(width), scale(height))
+    }
+}
+
+// system moving the render scale towards the frame time target
+pub struct DynamicResolutionSystem {
+    // smoothed frame time in milliseconds
+    average: Option<f32>,
+    // seconds left before the scale may change again
+    cooldown: f32,
+}
+
+impl Default for DynamicResolutionSystem {
+    fn default() -> Self {
+        DynamicResolutionSystem {
+            average: None,
+            cooldown: 0.0,
+        }
+    }
+}
+
+impl DynamicResolutionSystem {
+    // changing the scale rebuilds the render graph, so don't do it too often
+    const COOLDOWN: f32 = 1.0;
+}
+
+impl<'s> System<'s> for DynamicResolutionSystem {
+    type SystemData = (
+        Read<'s, Time>,
+        Read<'s, RenderScaleConfig>,
+        Write<'s, RenderScale>,
+    );
+
+    fn run(&mut self, (time, config, mut render_scale): Self::SystemData) {
+        if !config.dynamic {
+            return;
+        }
+
+        let frame_time = time.delta_real_seconds() * 1000.0;
+        let average = match self.average {
+            Some(average) => average * 0.9 + frame_time * 0.1,
+            None => frame_time,
+        };
+        self.average = Some(average);
+
+        self.cooldown -= time.delta_real_seconds();
+        if self.cooldown > 0.0 {
+            return;
+        }
+
+        // leave a band around the target so the scale doesn't oscillate
+        let scale = if average > config.target_frame_time * 1.05 {
+            render_scale.scale - RenderScale::STEP
+        } else if average < config.target_frame_time * 0.85 {
+            render_scale.scale + RenderScale::STEP
+        } else {
+            return;
+        };
+        let scale = RenderScale::new(scale.max(config.min_scale).min(config.max_scale));
+        if scale != *render_scale {
+            log::info!(
+                "Render scale {:.2} -> {:.2} ({:.2}ms frame time)",
+                render_scale.scale,
+                scale.scale,
+                average
+            );
+            *render_scale = scale;
+            self.cooldown = Self::COOLDOWN;
+            self.average = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RenderScale, RenderScaleConfig};
+    use crate::upscale::UpscaleQuality;
+
+    #[test]
+    fn apply_below_one() {
+        assert_eq!(RenderScale::new(0.5).apply(1920, 1080), (960, 540));
+        assert_eq!(RenderScale::new(0.75).apply(1280, 720), (960, 540));
+    }
+
+    #[test]
+    fn apply_above_one() {
+        assert_eq!(RenderScale::new(1.5).apply(1280, 720), (1920, 1080));
+        assert_eq!(RenderScale::new(2.0).apply(1280, 720), (2560, 1440));
+    }
+
+    #[test]
+    fn apply_is_at_least_a_pixel() {
+        assert_eq!(RenderScale::new(0.25).apply(1, 3), (1, 1));
+    }
+
+    #[test]
+    fn presets_are_exact() {
+        let config = |quality| RenderScaleConfig {
+            quality: Some(quality),
+            ..RenderScaleConfig::default()
+        };
+        assert_eq!(config(UpscaleQuality::UltraQuality).initial_scale().apply(1300, 1300), (1000, 1000));
+        assert_eq!(config(UpscaleQuality::Quality).initial_scale().apply(1920, 1080), (1280, 720));
+        assert_eq!(config(UpscaleQuality::Balanced).initial_scale().apply(1700, 850), (1000, 500));
+        assert_eq!(config(UpscaleQuality::Performance).initial_scale().apply(3840, 2160), (1920, 1080));
+        // the scale itself without a preset
+        assert_eq!(RenderScaleConfig::default().initial_scale(), RenderScale::new(1.0));
+    }
+
+    #[test]
+    fn scale_is_quantized() {
+        assert!((RenderScale::new(0.52).scale - 0.5).abs() < 1e-6);
+        assert!((RenderScale::new(0.53).scale - 0.55).abs() < 1e-6);
+        assert_eq!(RenderScale::new(0.52), RenderScale::new(0.48));
+    }
+}
@@ -0,0 +1,73 @@
+// anti-aliasing stress test scenes
+// the bundled scenes under assets/scenes each target a case fxaa struggles with, they can be picked
+// with --scene and cycled at runtime, which deletes the current scene and loads the next prefab
+
+use amethyst::{
+    assets::PrefabData,
+    core::{
+        math::{Unit, Vector3},
+        Time, Transform,
+    },
+    derive::PrefabData,
+    ecs::prelude::{Component, DenseVecStorage, Entity, Join, Read, ReadStorage, System, WriteStorage},
+    Error,
+};
+use serde::{Deserialize, Serialize};
+
+/// Scenes cycled through with the `next_scene` action, relative to the assets directory.
+pub const SCENES: [&str; 7] = [
+    "scene.ron",
+    "scenes/wires.ron",
+    "scenes/checkerboard.ron",
+    "scenes/horizon.ron",
+    "scenes/foliage.ron",
+    "scenes/text.ron",
+    "scenes/rotating.ron",
+];
+
+/// Path of a scene given on the command line, bundled scenes can be named without their path.
+pub fn scene_path(name: &str) -> String {
+    if name.ends_with(".ron") {
+        name.to_string()
+    } else {
+        format!("scenes/{}.ron", name)
+    }
+}
+
+/// Scene following `current` in the list, starting over at the first one.
+pub fn next_scene(current: &str) -> &'static str {
+    let index = SCENES.iter().position(|scene| *scene == current);
+    SCENES[index.map_or(0, |index| (index + 1) % SCENES.len())]
+}
+
+/// Spins an entity around an axis, in radians per second.
+#[derive(Clone, Debug, Deserialize, Serialize, PrefabData)]
+#[prefab(Component)]
+pub struct Rotate {
+    pub axis: Vector3<f32>,
+    pub speed: f32,
+}
+
+impl Component for Rotate {
+    type Storage = DenseVecStorage<Self>;
+}
+
+// system turning the entities with a Rotate component
+pub struct RotateSystem;
+
+impl<'s> System<'s> for RotateSystem {
+    type SystemData = (
+        Read<'s, Time>,
+        ReadStorage<'s, Rotate>,
+        WriteStorage<'s, Transform>,
+    );
+
+    fn run(&mut self, (time, rotates, mut transforms): Self::SystemData) {
+        for (rotate, transform) in (&rotates, &mut transforms).join() {
+            transform.append_rotation(
+                Unit::new_normalize(rotate.axis),
+                rotate.speed * time.delta_seconds(),
+            );
+        }
+    }
+}
@@ -0,0 +1,466 @@
+// screenshot capture node
+// copies the final anti-aliased image (and optionally the aliased input of the fxaa pass) into
+// host visible buffers at the end of the frame and writes them out as png once the frame is done
+
+use amethyst::{ecs::World, log};
+use rendy::{
+    command::{
+        CommandBuffer, CommandPool, ExecutableState, Family, Fence, IndividualReset, MultiShot,
+        PendingState, PrimaryLevel, Queue, QueueType, SimultaneousUse, Submission, Submit,
+    },
+    factory::Factory,
+    frame::Frames,
+    graph::{
+        gfx_acquire_barriers, gfx_release_barriers, GraphContext, ImageAccess, Node, NodeBuffer,
+        NodeDesc, NodeImage,
+    },
+    hal::{self, format::Format},
+    memory,
+    resource::{Buffer, BufferInfo, Escape},
+};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Screenshot configuration, loaded from `config/screenshot.ron`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScreenshotConfig {
+    /// Directory the png files are written to, created if it doesn't exist.
+    pub directory: PathBuf,
+    /// Always write the aliased image next to the anti-aliased one.
+    pub capture_both: bool,
+}
+
+impl Default for ScreenshotConfig {
+    fn default() -> Self {
+        ScreenshotConfig {
+            directory: PathBuf::from("screenshots"),
+            capture_both: false,
+        }
+    }
+}
+
+/// Resource holding the screenshot that should be taken on the next rendered frame.
+#[derive(Debug, Default)]
+pub struct ScreenshotQueue {
+    pending: Option<ScreenshotRequest>,
+}
+
+impl ScreenshotQueue {
+    /// Capture the next frame, with `both` also writing the image before fxaa was applied.
+    pub fn request(&mut self, both: bool) {
+        let both = both || self.pending.map_or(false, |r| r.both);
+        self.pending = Some(ScreenshotRequest { both });
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ScreenshotRequest {
+    both: bool,
+}
+
+type CaptureBuffer<B> = CommandBuffer<
+    B,
+    QueueType,
+    PendingState<ExecutableState<MultiShot<SimultaneousUse>>>,
+    PrimaryLevel,
+    IndividualReset,
+>;
+
+// recorded copy for a single frame in flight
+#[derive(Debug)]
+struct FrameCapture<B: hal::Backend> {
+    buffers: Vec<Escape<Buffer<B>>>,
+    submit: Submit<B, SimultaneousUse>,
+    command_buffer: CaptureBuffer<B>,
+    pending: Option<PendingCapture>,
+}
+
+#[derive(Debug)]
+struct PendingCapture {
+    frame: u64,
+    request: ScreenshotRequest,
+    directory: PathBuf,
+}
+
+// size and channel order of a captured image
+#[derive(Clone, Copy, Debug)]
+struct CaptureImage {
+    width: u32,
+    height: u32,
+    bgra: bool,
+}
+
+impl CaptureImage {
+    fn size(&self) -> u64 {
+        self.width as u64 * self.height as u64 * 4
+    }
+}
+
+/// Node reading back the images passed to it, the first being the final image and the second the
+/// image before fxaa.
+#[derive(Debug, Default)]
+pub struct CaptureDesc {
+    // the second image is the full resolution supersampled frame instead
+    supersampled: bool,
+    // only the final image is passed, which wasn't post-processed
+    unprocessed: bool,
+}
+
+impl CaptureDesc {
+    /// Capture of the supersampling mode, the second image being the frame before downsampling.
+    pub fn supersampled() -> Self {
+        CaptureDesc {
+            supersampled: true,
+            ..Default::default()
+        }
+    }
+
+    /// Capture of the fallback graph without post-processing, reading back only the final image.
+    pub fn unprocessed() -> Self {
+        CaptureDesc {
+            unprocessed: true,
+            ..Default::default()
+        }
+    }
+
+    fn image_count(&self) -> usize {
+        if self.unprocessed {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+// file name suffixes of the two captured images
+const NAMES: [&str; 2] = ["antialiased", "aliased"];
+const SUPERSAMPLED_NAMES: [&str; 2] = ["downsampled", "supersampled"];
+const UNPROCESSED_NAMES: [&str; 2] = ["unprocessed", "unprocessed"];
+
+#[derive(Debug)]
+pub struct Capture<B: hal::Backend> {
+    pool: CommandPool<B, QueueType, IndividualReset>,
+    idle_submit: Submit<B, SimultaneousUse>,
+    idle_buffer: CaptureBuffer<B>,
+    frames: Vec<FrameCapture<B>>,
+    images: Vec<CaptureImage>,
+    names: [&'static str; 2],
+    // writer threads with a flag set once they are done
+    writers: Vec<(JoinHandle<()>, Arc<AtomicBool>)>,
+}
+
+impl<B> NodeDesc<B, World> for CaptureDesc
+where
+    B: hal::Backend,
+{
+    type Node = Capture<B>;
+
+    fn images(&self) -> Vec<ImageAccess> {
+        let access = ImageAccess {
+            access: hal::image::Access::TRANSFER_READ,
+            usage: hal::image::Usage::TRANSFER_SRC,
+            layout: hal::image::Layout::TransferSrcOptimal,
+            stages: hal::pso::PipelineStage::TRANSFER,
+        };
+        vec![access; self.image_count()]
+    }
+
+    fn build<'a>(
+        self,
+        ctx: &GraphContext<B>,
+        factory: &mut Factory<B>,
+        family: &mut Family<B>,
+        _queue: usize,
+        _world: &World,
+        buffers: Vec<NodeBuffer>,
+        images: Vec<NodeImage>,
+    ) -> Result<Capture<B>, failure::Error> {
+        assert!(buffers.is_empty());
+        assert!(images.len() == self.image_count());
+
+        let mut pool = factory
+            .create_command_pool(family)?
+            .with_individual_reset();
+
+        let captures = images
+            .iter()
+            .map(|node_image| {
+                let image = ctx.get_image(node_image.id).expect("Capture image missing");
+                let extent = image.kind().extent();
+                CaptureImage {
+                    width: extent.width,
+                    height: extent.height,
+                    bgra: match image.format() {
+                        Format::Bgra8Unorm | Format::Bgra8Srgb => true,
+                        _ => false,
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // records the barriers the graph expects and, if a destination is given, the copies
+        let mut record = |targets: Option<&[Escape<Buffer<B>>]>| unsafe {
+            let command_buffer = pool.allocate_buffers(1).remove(0);
+            let mut command_buffer = command_buffer.begin(MultiShot(SimultaneousUse), ());
+            let mut encoder = command_buffer.encoder();
+
+            let (stages, barriers) = gfx_acquire_barriers(ctx, None, images.iter());
+            encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+
+            if let Some(targets) = targets {
+                for ((node_image, capture), target) in images.iter().zip(&captures).zip(targets) {
+                    let image = ctx.get_image(node_image.id).unwrap();
+                    encoder.copy_image_to_buffer(
+                        image.raw(),
+                        hal::image::Layout::TransferSrcOptimal,
+                        target.raw(),
+                        Some(hal::command::BufferImageCopy {
+                            buffer_offset: 0,
+                            buffer_width: capture.width,
+                            buffer_height: capture.height,
+                            image_layers: hal::image::SubresourceLayers {
+                                aspects: hal::format::Aspects::COLOR,
+                                level: 0,
+                                layers: 0..1,
+                            },
+                            image_offset: hal::image::Offset::ZERO,
+                            image_extent: hal::image::Extent {
+                                width: capture.width,
+                                height: capture.height,
+                                depth: 1,
+                            },
+                        }),
+                    );
+                }
+            }
+
+            let (stages, barriers) = gfx_release_barriers(ctx, None, images.iter());
+            encoder.pipeline_barrier(stages, hal::memory::Dependencies::empty(), barriers);
+
+            command_buffer.finish().submit()
+        };
+
+        let (idle_submit, idle_buffer) = record(None);
+
+        let mut frames = Vec::with_capacity(ctx.frames_in_flight as usize);
+        for _ in 0..ctx.frames_in_flight {
+            let buffers = captures
+                .iter()
+                .map(|capture| {
+                    factory.create_buffer(
+                        BufferInfo {
+                            size: capture.size(),
+                            usage: hal::buffer::Usage::TRANSFER_DST,
+                        },
+                        memory::Download,
+                    )
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let (submit, command_buffer) = record(Some(&buffers));
+            frames.push(FrameCapture {
+                buffers,
+                submit,
+                command_buffer,
+                pending: None,
+            });
+        }
+
+        Ok(Capture {
+            pool,
+            idle_submit,
+            idle_buffer,
+            frames,
+            images: captures,
+            names: if self.unprocessed {
+                UNPROCESSED_NAMES
+            } else if self.supersampled {
+                SUPERSAMPLED_NAMES
+            } else {
+                NAMES
+            },
+            writers: Vec::new(),
+        })
+    }
+}
+
+impl<B> Capture<B>
+where
+    B: hal::Backend,
+{
+    // read back every capture whose frame is below `complete` and hand it to a writer thread
+    unsafe fn collect(&mut self, factory: &Factory<B>, complete: u64) {
+        'frames: for frame in &mut self.frames {
+            let ready = frame.pending.as_ref().map_or(false, |p| p.frame < complete);
+            if !ready {
+                continue;
+            }
+            let pending = frame.pending.take().unwrap();
+
+            let mut pixels = Vec::with_capacity(self.images.len());
+            for (buffer, capture) in frame.buffers.iter_mut().zip(&self.images) {
+                let size = capture.size();
+                let mut data = match buffer.map(factory.device(), 0..size) {
+                    Ok(mut mapped) => match mapped.read::<u8>(factory.device(), 0..size) {
+                        Ok(data) => data.to_vec(),
+                        Err(err) => {
+                            log::error!("Failed to read screenshot buffer: {}", err);
+                            continue 'frames;
+                        }
+                    },
+                    Err(err) => {
+                        log::error!("Failed to map screenshot buffer: {}", err);
+                        continue 'frames;
+                    }
+                };
+                if capture.bgra {
+                    for pixel in data.chunks_exact_mut(4) {
+                        pixel.swap(0, 2);
+                    }
+                }
+                pixels.push(data);
+            }
+
+            // join the writers that are done, so their handles don't pile up over a session
+            let (done, running) = self
+                .writers
+                .drain(..)
+                .partition::<Vec<_>, _>(|(_, done)| done.load(Ordering::Acquire));
+            for (writer, _) in done {
+                let _ = writer.join();
+            }
+            self.writers = running;
+
+            // encode on a separate thread so it doesn't stall the frame
+            let done = Arc::new(AtomicBool::new(false));
+            let finished = Arc::clone(&done);
+            let (images, names) = (self.images.clone(), self.names);
+            let writer = std::thread::spawn(move || {
+                write_pngs(pending, images, names, pixels);
+                finished.store(true, Ordering::Release);
+            });
+            self.writers.push((writer, done));
+        }
+    }
+}
+
+// write the captured pixels into a png per image
+fn write_pngs(
+    pending: PendingCapture,
+    images: Vec<CaptureImage>,
+    names: [&'static str; 2],
+    pixels: Vec<Vec<u8>>,
+) {
+    if let Err(err) = std::fs::create_dir_all(&pending.directory) {
+        log::error!(
+            "Failed to create screenshot directory {:?}: {}",
+            pending.directory,
+            err
+        );
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let names = if pending.request.both { &names[..] } else { &names[..1] };
+
+    for ((name, capture), data) in names.iter().zip(&images).zip(&pixels) {
+        let path = pending
+            .directory
+            .join(format!("fxaa-{}-{}.png", timestamp, name));
+        match image::save_buffer(
+            &path,
+            data,
+            capture.width,
+            capture.height,
+            image::ColorType::RGBA(8),
+        ) {
+            Ok(()) => log::info!("Saved screenshot to {:?}", path),
+            Err(err) => log::error!("Failed to save screenshot {:?}: {}", path, err),
+        }
+    }
+}
+
+impl<B> Node<B, World> for Capture<B>
+where
+    B: hal::Backend,
+{
+    type Capability = QueueType;
+    type Desc = CaptureDesc;
+
+    unsafe fn run<'a>(
+        &mut self,
+        _ctx: &GraphContext<B>,
+        factory: &Factory<B>,
+        queue: &mut Queue<B>,
+        world: &World,
+        frames: &Frames<B>,
+        waits: &[(&'a B::Semaphore, hal::pso::PipelineStage)],
+        signals: &[&'a B::Semaphore],
+        fence: Option<&mut Fence<B>>,
+    ) {
+        self.collect(factory, frames.complete_upper_bound());
+
+        let index = frames.next().index();
+        let slot = (index % self.frames.len() as u64) as usize;
+
+        // only take the request when this frame's buffers are free again
+        let request = if self.frames[slot].pending.is_none() {
+            world
+                .try_fetch_mut::<ScreenshotQueue>()
+                .and_then(|mut queue| queue.pending.take())
+        } else {
+            None
+        };
+
+        let submit = match request {
+            Some(request) => {
+                let directory = world
+                    .try_fetch::<ScreenshotConfig>()
+                    .map(|config| config.directory.clone())
+                    .unwrap_or_else(|| ScreenshotConfig::default().directory);
+                let frame = &mut self.frames[slot];
+                frame.pending = Some(PendingCapture {
+                    frame: index,
+                    request,
+                    directory,
+                });
+                &frame.submit
+            }
+            None => &self.idle_submit,
+        };
+
+        queue.submit(
+            Some(
+                Submission::new()
+                    .submits(Some(submit))
+                    .wait(waits.iter().cloned())
+                    .signal(signals.iter()),
+            ),
+            fence,
+        );
+    }
+
+    unsafe fn dispose(mut self, factory: &mut Factory<B>, _world: &World) {
+        // all frames are finished by now, so write out what was captured last before exiting
+        self.collect(factory, u64::max_value());
+        for (writer, _) in self.writers.drain(..) {
+            let _ = writer.join();
+        }
+
+        let mut command_buffers = vec![self.idle_buffer.mark_complete()];
+        command_buffers.extend(
+            self.frames
+                .into_iter()
+                .map(|frame| frame.command_buffer.mark_complete()),
+        );
+        self.pool.free_buffers(command_buffers);
+        factory.destroy_command_pool(self.pool);
+    }
+}
@@ -0,0 +1,41 @@
+// contrast adaptive sharpening
+// a full-screen pass after fxaa winning back texture detail, its strength set in the fxaa settings
+
+use amethyst::ecs::World;
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, PassInfo};
+use glsl_layout::*;
+
+const FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/sharpen.frag.spv");
+
+/// Sharpening pass writing an image of `target_size`, with `FxaaSettings::sharpen_strength`.
+pub fn pass(target_size: (u32, u32)) -> FullscreenPassDesc<SharpenArgs> {
+    FullscreenPassDesc::new("Sharpen", FRAGMENT_SPIRV, target_size, uniform_args)
+        .with_input(FullscreenInput::linear())
+        .with_uniform_layout(crate::uniform_layout!(SharpenArgs { source_size, target_size, strength }))
+}
+
+fn uniform_args(world: &World, info: &PassInfo) -> SharpenArgs {
+    let strength = world
+        .try_fetch::<crate::FxaaSettings>()
+        .map_or(0.0, |settings| settings.sharpen_strength);
+    let (source_size, target_size) = info.sizes();
+    SharpenArgs {
+        source_size,
+        target_size,
+        strength,
+    }
+}
+
+/// layout(std140, set = 0, binding = 0) uniform SharpenArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform float strength;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct SharpenArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    // 0 leaves the image untouched, 1 is the strongest cas setting
+    pub strength: float,
+}
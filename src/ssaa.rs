@@ -0,0 +1,141 @@
+// supersampling reference mode
+// the main pass renders at an integer multiple of the window size and this pipeline filters it down
+// into the window sized image in place of the fxaa pass
+
+use amethyst::ecs::World;
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, PassInfo};
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+
+const FRAGMENT_SPIRV: &[u8] = include_bytes!("../assets/shader/downsample.frag.spv");
+
+/// Downsample pass filtering the supersampled color image into an image of `target_size`. The
+/// shader fetches texels directly, filtering is done by its weights.
+pub fn pass(target_size: (u32, u32), factor: u32) -> FullscreenPassDesc<DownsampleArgs> {
+    let uniform_args = move |world: &World, info: &PassInfo| {
+        let filter = world
+            .try_fetch::<SsaaSettings>()
+            .map_or(DownsampleFilter::default(), |settings| settings.filter);
+        let (source_size, target_size) = info.sizes();
+        DownsampleArgs {
+            source_size,
+            target_size,
+            factor: factor as i32,
+            filter_mode: filter.shader_value().into(),
+        }
+    };
+    FullscreenPassDesc::new("Downsample", FRAGMENT_SPIRV, target_size, uniform_args)
+        .with_input(FullscreenInput::nearest())
+        .with_uniform_layout(crate::uniform_layout!(DownsampleArgs {
+            source_size,
+            target_size,
+            factor,
+            filter_mode,
+        }))
+}
+
+/// Filter the supersampled image is reduced with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum DownsampleFilter {
+    /// Average of the samples inside each pixel.
+    Box,
+    /// Lanczos2, sharper at the cost of some ringing.
+    Lanczos,
+}
+
+impl Default for DownsampleFilter {
+    fn default() -> Self {
+        DownsampleFilter::Box
+    }
+}
+
+impl DownsampleFilter {
+    // value of the matching FILTER_* define in downsample.frag
+    fn shader_value(self) -> u32 {
+        match self {
+            DownsampleFilter::Box => 0,
+            DownsampleFilter::Lanczos => 1,
+        }
+    }
+}
+
+/// Supersampling settings, loaded from `config/ssaa.ron`. Replaces fxaa while enabled.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SsaaSettings {
+    pub enabled: bool,
+    /// Samples per pixel along each axis, 2 to 4.
+    pub factor: u32,
+    pub filter: DownsampleFilter,
+}
+
+impl Default for SsaaSettings {
+    fn default() -> Self {
+        SsaaSettings {
+            enabled: false,
+            factor: 2,
+            filter: DownsampleFilter::default(),
+        }
+    }
+}
+
+impl SsaaSettings {
+    pub const MIN_FACTOR: u32 = 2;
+    pub const MAX_FACTOR: u32 = 4;
+
+    /// Factor the main pass is rendered at, if supersampling is enabled.
+    pub fn active_factor(&self) -> Option<u32> {
+        if self.enabled {
+            Some(self.factor.max(Self::MIN_FACTOR).min(Self::MAX_FACTOR))
+        } else {
+            None
+        }
+    }
+}
+
+/// Largest factor up to `factor` whose supersampled image of a `window_size` window fits in images
+/// of `max_size` pixels per side, none if not even the minimum factor fits.
+pub fn fitting_factor(factor: u32, window_size: (u32, u32), max_size: u32) -> Option<u32> {
+    let largest = max_size / window_size.0.max(window_size.1).max(1);
+    Some(factor.min(largest)).filter(|factor| *factor >= SsaaSettings::MIN_FACTOR)
+}
+
+/// layout(std140, set = 0, binding = 0) uniform DownsampleArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform int factor;
+///    uniform uint filter_mode;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct DownsampleArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    pub factor: int,
+    // DownsampleFilter as integer
+    pub filter_mode: uint,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fitting_factor;
+
+    #[test]
+    fn factor_fits() {
+        assert_eq!(fitting_factor(4, (1920, 1080), 16384), Some(4));
+        assert_eq!(fitting_factor(2, (1920, 1080), 4096), Some(2));
+    }
+
+    #[test]
+    fn factor_is_clamped_to_the_image_limit() {
+        assert_eq!(fitting_factor(4, (1920, 1080), 8192), Some(4));
+        assert_eq!(fitting_factor(4, (2560, 1440), 8192), Some(3));
+        assert_eq!(fitting_factor(4, (3840, 2160), 8192), Some(2));
+    }
+
+    #[test]
+    fn factor_below_the_minimum_is_rejected() {
+        assert_eq!(fitting_factor(4, (3840, 2160), 4096), None);
+        assert_eq!(fitting_factor(2, (1080, 1920), 2048), None);
+    }
+}
@@ -0,0 +1,115 @@
+// edge adaptive upscaling
+// spatial upscaler and sharpener modelled on amd fsr 1, turning the low resolution output of fxaa
+// into the window sized image when the render scale is below one
+
+use amethyst::ecs::World;
+use crate::fullscreen::{FullscreenInput, FullscreenPassDesc, PassInfo};
+use crate::scale::RenderScaleConfig;
+use glsl_layout::*;
+use serde::{Deserialize, Serialize};
+
+const EASU_SPIRV: &[u8] = include_bytes!("../assets/shader/easu.frag.spv");
+const RCAS_SPIRV: &[u8] = include_bytes!("../assets/shader/rcas.frag.spv");
+
+/// How the scaled main pass is brought to the window size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Upscaler {
+    /// The first post-processing pass samples the scaled image bilinearly.
+    Bilinear,
+    /// Edge adaptive upscaling followed by sharpening, after fxaa at the render resolution.
+    Fsr,
+}
+
+impl Default for Upscaler {
+    fn default() -> Self {
+        Upscaler::Bilinear
+    }
+}
+
+/// Upscaling factor presets, setting the render scale to its inverse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum UpscaleQuality {
+    /// 1.3x
+    UltraQuality,
+    /// 1.5x
+    Quality,
+    /// 1.7x
+    Balanced,
+    /// 2x
+    Performance,
+}
+
+impl UpscaleQuality {
+    /// Window size over render size along each axis.
+    pub fn factor(self) -> f32 {
+        match self {
+            UpscaleQuality::UltraQuality => 1.3,
+            UpscaleQuality::Quality => 1.5,
+            UpscaleQuality::Balanced => 1.7,
+            UpscaleQuality::Performance => 2.0,
+        }
+    }
+
+    pub fn scale(self) -> f32 {
+        1.0 / self.factor()
+    }
+}
+
+/// Edge adaptive upscaling pass reading the render resolution image and writing an image of
+/// `target_size`. It fetches single texels, so the input isn't filtered.
+pub fn easu_pass(target_size: (u32, u32)) -> FullscreenPassDesc<EasuArgs> {
+    let uniform_args = |_: &World, info: &PassInfo| {
+        let (source_size, target_size) = info.sizes();
+        EasuArgs {
+            source_size,
+            target_size,
+        }
+    };
+    FullscreenPassDesc::new("EASU", EASU_SPIRV, target_size, uniform_args)
+        .with_input(FullscreenInput::nearest())
+        .with_uniform_layout(crate::uniform_layout!(EasuArgs { source_size, target_size }))
+}
+
+/// Sharpening pass following `easu_pass` at the same size, with `RenderScaleConfig::sharpness`.
+pub fn rcas_pass(target_size: (u32, u32)) -> FullscreenPassDesc<RcasArgs> {
+    FullscreenPassDesc::new("RCAS", RCAS_SPIRV, target_size, rcas_args)
+        .with_input(FullscreenInput::nearest())
+        .with_uniform_layout(crate::uniform_layout!(RcasArgs { source_size, target_size, sharpness }))
+}
+
+fn rcas_args(world: &World, info: &PassInfo) -> RcasArgs {
+    let sharpness = world
+        .try_fetch::<RenderScaleConfig>()
+        .map_or(0.2, |config| config.sharpness);
+    let (source_size, target_size) = info.sizes();
+    RcasArgs {
+        source_size,
+        target_size,
+        sharpness,
+    }
+}
+
+/// layout(std140, set = 0, binding = 0) uniform EasuArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct EasuArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+}
+
+/// layout(std140, set = 0, binding = 0) uniform RcasArgs {
+///    uniform vec2 source_size;
+///    uniform vec2 target_size;
+///    uniform float sharpness;
+/// };
+#[derive(Clone, Copy, Debug, AsStd140)]
+#[repr(C, align(4))]
+pub struct RcasArgs {
+    pub source_size: vec2,
+    pub target_size: vec2,
+    // reduction in stops, 0 is the strongest
+    pub sharpness: float,
+}